@@ -0,0 +1,11 @@
+-- Add migration script here
+BEGIN;
+    ALTER TABLE subscription_tokens
+        ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+    ALTER TABLE subscription_tokens ADD COLUMN expires_at timestamptz NULL;
+    UPDATE subscription_tokens
+        SET expires_at = created_at + interval '24 hours'
+        WHERE expires_at IS NULL;
+    ALTER TABLE subscription_tokens ALTER COLUMN expires_at SET NOT NULL;
+    ALTER TABLE subscription_tokens ADD COLUMN used_at timestamptz NULL;
+COMMIT;
@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE email_outbox(
+    email_outbox_id uuid NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    n_attempts INT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    last_error TEXT NULL,
+    failed_at timestamptz NULL,
+    PRIMARY KEY (email_outbox_id)
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+BEGIN;
+    ALTER TABLE newsletter_issues
+        ADD COLUMN author_id uuid NULL REFERENCES users (user_id),
+        ADD COLUMN created_at timestamptz NULL,
+        ADD COLUMN status TEXT NOT NULL DEFAULT 'published',
+        ADD COLUMN n_recipients INT NOT NULL DEFAULT 0;
+    UPDATE newsletter_issues SET created_at = published_at;
+    ALTER TABLE newsletter_issues ALTER COLUMN created_at SET NOT NULL;
+    ALTER TABLE newsletter_issues ALTER COLUMN created_at SET DEFAULT now();
+COMMIT;
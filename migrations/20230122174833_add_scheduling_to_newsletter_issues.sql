@@ -0,0 +1,5 @@
+-- Add migration script here
+BEGIN;
+    ALTER TABLE newsletter_issues ADD COLUMN scheduled_for timestamptz NULL;
+    ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+COMMIT;
@@ -0,0 +1,21 @@
+-- Add migration script here
+BEGIN;
+    CREATE TABLE deliveries(
+        newsletter_issue_id uuid NOT NULL
+            REFERENCES newsletter_issues (newsletter_issue_id),
+        subscriber_email TEXT NOT NULL,
+        status TEXT NOT NULL,
+        n_attempts INT NOT NULL DEFAULT 0,
+        last_error TEXT NULL,
+        provider_message_id TEXT NULL,
+        created_at timestamptz NOT NULL DEFAULT now(),
+        updated_at timestamptz NOT NULL DEFAULT now(),
+        PRIMARY KEY (newsletter_issue_id, subscriber_email)
+    );
+    ALTER TABLE issue_delivery_queue
+        ADD COLUMN n_retries INT NOT NULL DEFAULT 0,
+        ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
+    INSERT INTO deliveries (newsletter_issue_id, subscriber_email, status)
+        SELECT newsletter_issue_id, subscriber_email, 'pending'
+        FROM issue_delivery_queue;
+COMMIT;
@@ -0,0 +1,9 @@
+-- Add migration script here
+BEGIN;
+    ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
+    -- The confirmation time of existing subscribers is unknown, fall back to
+    -- when they signed up.
+    UPDATE subscriptions
+        SET confirmed_at = subscribed_at
+        WHERE status = 'confirmed';
+COMMIT;
@@ -0,0 +1,29 @@
+-- Add migration script here
+BEGIN;
+    ALTER TABLE newsletter_issues
+        ADD COLUMN tracking_enabled BOOLEAN NOT NULL DEFAULT false;
+    ALTER TABLE deliveries ADD COLUMN tracking_token TEXT NULL;
+    UPDATE deliveries
+        SET tracking_token = md5(random()::text || newsletter_issue_id::text || subscriber_email);
+    ALTER TABLE deliveries ALTER COLUMN tracking_token SET NOT NULL;
+    ALTER TABLE deliveries ADD CONSTRAINT deliveries_tracking_token_key UNIQUE (tracking_token);
+    CREATE TABLE issue_links(
+        newsletter_issue_id uuid NOT NULL
+            REFERENCES newsletter_issues (newsletter_issue_id),
+        link_index INT NOT NULL,
+        url TEXT NOT NULL,
+        PRIMARY KEY (newsletter_issue_id, link_index)
+    );
+    CREATE TABLE tracking_events(
+        event_id uuid PRIMARY KEY,
+        newsletter_issue_id uuid NOT NULL,
+        subscriber_email TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        link_index INT NULL,
+        occurred_at timestamptz NOT NULL DEFAULT now(),
+        FOREIGN KEY (newsletter_issue_id, subscriber_email)
+            REFERENCES deliveries (newsletter_issue_id, subscriber_email)
+    );
+    CREATE INDEX tracking_events_issue_kind_idx
+        ON tracking_events (newsletter_issue_id, kind);
+COMMIT;
@@ -0,0 +1,15 @@
+-- Add migration script here
+BEGIN;
+    CREATE TABLE email_events (
+        event_id uuid PRIMARY KEY,
+        -- Providers retry webhooks, so the same event can be reported twice.
+        provider_event_id TEXT UNIQUE,
+        subscriber_email TEXT NOT NULL,
+        event TEXT NOT NULL,
+        bounce_type TEXT,
+        reason TEXT,
+        occurred_at timestamptz NOT NULL,
+        received_at timestamptz NOT NULL DEFAULT now()
+    );
+    CREATE INDEX email_events_subscriber_email_idx ON email_events (subscriber_email);
+COMMIT;
@@ -0,0 +1,26 @@
+-- Add migration script here
+BEGIN;
+    CREATE TABLE suppressions (
+        suppression_id uuid PRIMARY KEY,
+        -- Either a single address or a whole domain, both lowercase.
+        address TEXT,
+        domain TEXT,
+        reason TEXT NOT NULL,
+        source TEXT NOT NULL,
+        created_at timestamptz NOT NULL DEFAULT now(),
+        -- Suppressions without an expiry are permanent.
+        expires_at timestamptz,
+        CHECK ((address IS NULL) <> (domain IS NULL))
+    );
+    CREATE UNIQUE INDEX suppressions_target_idx ON suppressions ((COALESCE(address, '@' || domain)));
+    CREATE INDEX suppressions_domain_idx ON suppressions (domain) WHERE domain IS NOT NULL;
+
+    CREATE TABLE suppressed_sends (
+        suppressed_send_id uuid PRIMARY KEY,
+        recipient TEXT NOT NULL,
+        -- `transactional` or `newsletter`.
+        kind TEXT NOT NULL,
+        newsletter_issue_id uuid REFERENCES newsletter_issues (newsletter_issue_id),
+        suppressed_at timestamptz NOT NULL DEFAULT now()
+    );
+COMMIT;
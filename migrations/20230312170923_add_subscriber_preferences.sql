@@ -0,0 +1,19 @@
+-- Add migration script here
+BEGIN;
+    -- An empty topic list means every topic.
+    ALTER TABLE subscriptions ADD COLUMN topics TEXT[] NOT NULL DEFAULT '{}';
+    ALTER TABLE subscriptions ADD COLUMN frequency TEXT NOT NULL DEFAULT 'every_issue';
+    ALTER TABLE subscriptions ADD COLUMN paused_until timestamptz;
+    ALTER TABLE subscriptions ADD COLUMN last_digest_at timestamptz;
+    -- Issues without a topic go to everyone.
+    ALTER TABLE newsletter_issues ADD COLUMN topic TEXT;
+
+    -- Issues waiting to go out in a subscriber's next weekly digest.
+    CREATE TABLE digest_entries (
+        subscriber_email TEXT NOT NULL,
+        newsletter_issue_id uuid NOT NULL
+            REFERENCES newsletter_issues (newsletter_issue_id),
+        created_at timestamptz NOT NULL DEFAULT now(),
+        PRIMARY KEY (subscriber_email, newsletter_issue_id)
+    );
+COMMIT;
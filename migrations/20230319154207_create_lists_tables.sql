@@ -0,0 +1,62 @@
+-- Add migration script here
+BEGIN;
+    CREATE TABLE lists(
+        list_id uuid NOT NULL PRIMARY KEY,
+        slug TEXT NOT NULL UNIQUE,
+        name TEXT NOT NULL,
+        created_at timestamptz NOT NULL
+    );
+    -- Everyone who subscribed so far is on the original list.
+    INSERT INTO lists (list_id, slug, name, created_at)
+    VALUES (gen_random_uuid(), 'newsletter', 'Our newsletter', now());
+
+    CREATE TABLE list_subscriptions(
+        list_id uuid NOT NULL
+            REFERENCES lists (list_id),
+        subscriber_id uuid NOT NULL
+            REFERENCES subscriptions (id),
+        status TEXT NOT NULL,
+        subscribed_at timestamptz NOT NULL,
+        confirmed_at timestamptz,
+        unsubscribed_at timestamptz,
+        PRIMARY KEY (list_id, subscriber_id)
+    );
+    -- Bounces and complaints stay on the subscriber, they were confirmed on
+    -- the list.
+    INSERT INTO list_subscriptions (
+        list_id,
+        subscriber_id,
+        status,
+        subscribed_at,
+        confirmed_at,
+        unsubscribed_at
+    )
+    SELECT
+        l.list_id,
+        s.id,
+        CASE
+            WHEN s.status IN ('pending_confirmation', 'unsubscribed') THEN s.status
+            ELSE 'confirmed'
+        END,
+        s.subscribed_at,
+        s.confirmed_at,
+        s.unsubscribed_at
+    FROM subscriptions s, lists l;
+
+    -- A confirmation link confirms the subscription to a single list.
+    ALTER TABLE subscription_tokens ADD COLUMN list_id uuid
+        REFERENCES lists (list_id);
+    UPDATE subscription_tokens SET list_id = (SELECT list_id FROM lists);
+    ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+
+    CREATE TABLE newsletter_issue_lists(
+        newsletter_issue_id uuid NOT NULL
+            REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+        list_id uuid NOT NULL
+            REFERENCES lists (list_id),
+        PRIMARY KEY (newsletter_issue_id, list_id)
+    );
+    INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+    SELECT i.newsletter_issue_id, l.list_id
+    FROM newsletter_issues i, lists l;
+COMMIT;
@@ -0,0 +1,16 @@
+-- Add migration script here
+BEGIN;
+    -- Mail clients load the open pixel every time an email is viewed, only the
+    -- first open of each delivery is kept.
+    DELETE FROM tracking_events e
+    USING tracking_events earlier
+    WHERE
+        e.kind = 'open' AND
+        earlier.kind = 'open' AND
+        earlier.newsletter_issue_id = e.newsletter_issue_id AND
+        earlier.subscriber_email = e.subscriber_email AND
+        (earlier.occurred_at, earlier.event_id) < (e.occurred_at, e.event_id);
+    CREATE UNIQUE INDEX tracking_events_first_open_idx
+        ON tracking_events (newsletter_issue_id, subscriber_email)
+        WHERE kind = 'open';
+COMMIT;
@@ -0,0 +1,6 @@
+-- Add migration script here
+BEGIN;
+    -- `transactional` or `digest`, recorded on the sends held back by a
+    -- suppression.
+    ALTER TABLE email_outbox ADD COLUMN kind TEXT NOT NULL DEFAULT 'transactional';
+COMMIT;
@@ -0,0 +1,200 @@
+use crate::configuration::Settings;
+use crate::domain::{IssueTemplates, SubscriberEmail, TemplateValues};
+use crate::email_outbox_worker::{enqueue_email, OutboxEmail};
+use crate::issue_delivery_worker::{append_to_body, ExecutionOutcome, HTML_FOOTER, TEXT_FOOTER};
+use crate::markdown::{body_content, HtmlLayout};
+use crate::routes::{preferences_link, unsubscribe_link};
+use crate::startup::get_connection_pool;
+use crate::suppression::SendKind;
+use chrono::{DateTime, Utc};
+use pulldown_cmark::escape::escape_html;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tracing::{field::display, Span};
+
+const DIGEST_TITLE: &str = "Your weekly digest";
+
+pub async fn run_digest_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let layout = configuration
+        .application
+        .newsletter_layout()
+        .map_err(anyhow::Error::msg)?;
+    worker_loop(connection_pool, configuration.application.base_url, layout).await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    base_url: String,
+    layout: HtmlLayout,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_queue_digest(&pool, &base_url, &layout).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+// Bundles the issues waiting for one subscriber whose last digest is at least
+// a week old into a single email, handed to the outbox.
+#[tracing::instrument(skip_all, fields(subscriber_email = tracing::field::Empty), err)]
+pub async fn try_queue_digest(
+    pool: &PgPool,
+    base_url: &str,
+    layout: &HtmlLayout,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let subscriber = match get_due_subscriber(&mut transaction).await? {
+        Some(subscriber) => subscriber,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current().record("subscriber_email", display(&subscriber.email));
+
+    let issues = get_digest_issues(&mut transaction, &subscriber.email).await?;
+    let values = TemplateValues {
+        name: subscriber.name.clone(),
+        unsubscribe_url: unsubscribe_link(base_url, &subscriber.unsubscribe_token),
+        preferences_url: preferences_link(base_url, &subscriber.unsubscribe_token),
+        confirmed_at: subscriber.confirmed_at,
+    };
+    let mut html_sections = Vec::new();
+    let mut text_sections = Vec::new();
+    for issue in &issues {
+        let templates = IssueTemplates::parse_lenient(&issue.html_content, &issue.text_content);
+        let mut title = String::new();
+        escape_html(&mut title, &issue.title)?;
+        html_sections.push(format!(
+            "<h2>{}</h2>\n{}",
+            title,
+            // Several issues share a single document.
+            body_content(&templates.html.render(&values))
+        ));
+        text_sections.push(format!(
+            "{}\n\n{}",
+            issue.title,
+            templates.text.render(&values)
+        ));
+    }
+
+    match SubscriberEmail::parse(subscriber.email.clone()) {
+        Ok(recipient) if !html_sections.is_empty() => {
+            let footers = IssueTemplates::parse(HTML_FOOTER, TEXT_FOOTER)
+                .expect("The footers are valid templates");
+            let html_content = append_to_body(
+                &layout.wrap(DIGEST_TITLE, &html_sections.join("\n<hr>\n")),
+                &footers.html.render(&values),
+            );
+            let text_content = format!(
+                "{}{}",
+                text_sections.join("\n\n---\n\n"),
+                footers.text.render(&values)
+            );
+            let email = OutboxEmail {
+                recipient,
+                subject: DIGEST_TITLE.into(),
+                html_content,
+                text_content,
+                kind: SendKind::Digest,
+            };
+            enqueue_email(&mut transaction, &email).await?;
+        }
+        Ok(_) => {}
+        Err(e) => tracing::error!(
+            error.message = %e,
+            "Skipping a digest. The stored email address is invalid"
+        ),
+    }
+    clear_digest(&mut transaction, &subscriber.email).await?;
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct DigestSubscriber {
+    email: String,
+    name: String,
+    unsubscribe_token: String,
+    confirmed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_due_subscriber(
+    transaction: &mut PgTransaction,
+) -> Result<Option<DigestSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        DigestSubscriber,
+        r#"
+        SELECT
+            email,
+            name,
+            unsubscribe_token,
+            COALESCE(confirmed_at, subscribed_at) as "confirmed_at!"
+        FROM subscriptions s
+        WHERE
+            status = 'confirmed' AND
+            (paused_until IS NULL OR paused_until <= now()) AND
+            COALESCE(last_digest_at, confirmed_at, subscribed_at) <= now() - interval '7 days' AND
+            EXISTS (SELECT 1 FROM digest_entries e WHERE e.subscriber_email = s.email)
+        ORDER BY COALESCE(last_digest_at, confirmed_at, subscribed_at)
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
+struct DigestIssue {
+    title: String,
+    html_content: String,
+    text_content: String,
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn get_digest_issues(
+    transaction: &mut PgTransaction,
+    subscriber_email: &str,
+) -> Result<Vec<DigestIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        DigestIssue,
+        r#"
+        SELECT i.title, i.html_content, i.text_content
+        FROM digest_entries e
+        JOIN newsletter_issues i ON i.newsletter_issue_id = e.newsletter_issue_id
+        WHERE e.subscriber_email = $1
+        ORDER BY i.published_at, e.created_at
+        "#,
+        subscriber_email,
+    )
+    .fetch_all(transaction)
+    .await
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn clear_digest(
+    transaction: &mut PgTransaction,
+    subscriber_email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM digest_entries WHERE subscriber_email = $1",
+        subscriber_email
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "UPDATE subscriptions SET last_digest_at = now() WHERE email = $1",
+        subscriber_email
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
@@ -1,7 +1,13 @@
 mod new_subscriber;
+mod newsletter_template;
 mod subscriber_email;
 mod subscriber_name;
+mod topic;
 
 pub use new_subscriber::NewSubscriber;
+pub use newsletter_template::{
+    ContentFormat, IssueTemplates, NewsletterTemplate, TemplateValues, TemplateVariable,
+};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use topic::Topic;
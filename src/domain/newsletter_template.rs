@@ -0,0 +1,372 @@
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TemplateVariable {
+    Name,
+    UnsubscribeUrl,
+    PreferencesUrl,
+    ConfirmedAt,
+}
+
+impl TemplateVariable {
+    const ALL: [TemplateVariable; 4] = [
+        TemplateVariable::Name,
+        TemplateVariable::UnsubscribeUrl,
+        TemplateVariable::PreferencesUrl,
+        TemplateVariable::ConfirmedAt,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TemplateVariable::Name => "name",
+            TemplateVariable::UnsubscribeUrl => "unsubscribe_url",
+            TemplateVariable::PreferencesUrl => "preferences_url",
+            TemplateVariable::ConfirmedAt => "confirmed_at",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.as_str() == s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentFormat {
+    Html,
+    Text,
+}
+
+// The values a template is rendered with, one set per recipient.
+#[derive(Debug, Clone)]
+pub struct TemplateValues {
+    pub name: String,
+    pub unsubscribe_url: String,
+    pub preferences_url: String,
+    pub confirmed_at: DateTime<Utc>,
+}
+
+impl TemplateValues {
+    fn get(&self, variable: TemplateVariable) -> String {
+        match variable {
+            TemplateVariable::Name => self.name.clone(),
+            TemplateVariable::UnsubscribeUrl => self.unsubscribe_url.clone(),
+            TemplateVariable::PreferencesUrl => self.preferences_url.clone(),
+            TemplateVariable::ConfirmedAt => self.confirmed_at.format("%B %-d, %Y").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Variable(TemplateVariable),
+}
+
+#[derive(Debug, Clone)]
+pub struct NewsletterTemplate {
+    format: ContentFormat,
+    segments: Vec<Segment>,
+    // Makes the placeholders impossible to write as literal text.
+    marker: String,
+}
+
+// What a template is made of, along with what keeps it from being valid.
+struct Tokens {
+    segments: Vec<Segment>,
+    unknown: Vec<String>,
+    unclosed: bool,
+}
+
+impl Tokens {
+    fn read(s: &str) -> Self {
+        let mut segments = Vec::new();
+        let mut unknown = Vec::new();
+        let mut unclosed = false;
+        let mut literal = String::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            literal.push_str(&rest[..start]);
+            let after_open = &rest[start + 2..];
+            if let Some(escaped) = after_open.strip_prefix("{{") {
+                literal.push_str("{{");
+                rest = escaped;
+                continue;
+            }
+            let end = match after_open.find("}}") {
+                Some(end) => end,
+                None => {
+                    unclosed = true;
+                    literal.push_str(&rest[start..]);
+                    rest = "";
+                    break;
+                }
+            };
+            let name = after_open[..end].trim();
+            match TemplateVariable::parse(name) {
+                Some(variable) => {
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Variable(variable));
+                }
+                None => {
+                    unknown.push(format!("`{}`", name));
+                    literal.push_str(&rest[start..start + 2 + end + 2]);
+                }
+            }
+            rest = &after_open[end + 2..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Self {
+            segments,
+            unknown,
+            unclosed,
+        }
+    }
+}
+
+impl NewsletterTemplate {
+    // Variables are written as `{{ name }}`, with optional inner whitespace,
+    // and a literal `{{` as `{{{{`. Anything between braces that is not a
+    // known variable is rejected.
+    pub fn parse(s: &str, format: ContentFormat) -> Result<Self, String> {
+        let Tokens {
+            segments,
+            unknown,
+            unclosed,
+        } = Tokens::read(s);
+        if unclosed {
+            return Err("A `{{` is never closed with a matching `}}`".into());
+        }
+        if !unknown.is_empty() {
+            let known: Vec<_> = TemplateVariable::ALL.iter().map(|v| v.as_str()).collect();
+            return Err(format!(
+                "Unknown template variables: {}. The available variables are: {}",
+                unknown.join(", "),
+                known.join(", ")
+            ));
+        }
+        Ok(Self::new(format, segments))
+    }
+
+    // Keeps anything that is not a known variable as it is. Meant for stored
+    // issues, which were validated when published or predate templating.
+    pub fn parse_lenient(s: &str, format: ContentFormat) -> Self {
+        Self::new(format, Tokens::read(s).segments)
+    }
+
+    fn new(format: ContentFormat, segments: Vec<Segment>) -> Self {
+        let mut rng = thread_rng();
+        let marker = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(16)
+            .collect();
+        Self {
+            format,
+            segments,
+            marker,
+        }
+    }
+
+    pub fn variables(&self) -> Vec<TemplateVariable> {
+        let mut variables: Vec<_> = self
+            .segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Variable(variable) => Some(*variable),
+                Segment::Literal(_) => None,
+            })
+            .collect();
+        variables.sort();
+        variables.dedup();
+        variables
+    }
+
+    pub fn render(&self, values: &TemplateValues) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.clone(),
+                Segment::Variable(variable) => self.escape(&values.get(*variable)),
+            })
+            .collect()
+    }
+
+    // The template with every variable replaced by a placeholder that the
+    // email provider fills in per recipient. HTML and plain text get different
+    // placeholders since only the former needs escaping, and both carry the
+    // template's marker so that escaped braces are never taken for one.
+    pub fn with_placeholders(&self) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.clone(),
+                Segment::Variable(variable) => self.placeholder(*variable),
+            })
+            .collect()
+    }
+
+    // The placeholder/value pairs matching `with_placeholders`.
+    pub fn substitutions(&self, values: &TemplateValues) -> Vec<(String, String)> {
+        self.variables()
+            .into_iter()
+            .map(|variable| {
+                (
+                    self.placeholder(variable),
+                    self.escape(&values.get(variable)),
+                )
+            })
+            .collect()
+    }
+
+    fn placeholder(&self, variable: TemplateVariable) -> String {
+        match self.format {
+            ContentFormat::Html => {
+                format!("{{{{{}|html|{}}}}}", variable.as_str(), self.marker)
+            }
+            ContentFormat::Text => format!("{{{{{}|{}}}}}", variable.as_str(), self.marker),
+        }
+    }
+
+    fn escape(&self, value: &str) -> String {
+        match self.format {
+            ContentFormat::Html => value
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+                .replace('\'', "&#39;"),
+            ContentFormat::Text => value.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IssueTemplates {
+    pub html: NewsletterTemplate,
+    pub text: NewsletterTemplate,
+}
+
+impl IssueTemplates {
+    pub fn parse(html_content: &str, text_content: &str) -> Result<Self, String> {
+        let html = NewsletterTemplate::parse(html_content, ContentFormat::Html)
+            .map_err(|e| format!("The HTML content is not a valid template. {}", e))?;
+        let text = NewsletterTemplate::parse(text_content, ContentFormat::Text)
+            .map_err(|e| format!("The plain text content is not a valid template. {}", e))?;
+        Ok(Self { html, text })
+    }
+
+    pub fn parse_lenient(html_content: &str, text_content: &str) -> Self {
+        Self {
+            html: NewsletterTemplate::parse_lenient(html_content, ContentFormat::Html),
+            text: NewsletterTemplate::parse_lenient(text_content, ContentFormat::Text),
+        }
+    }
+
+    pub fn substitutions(&self, values: &TemplateValues) -> Vec<(String, String)> {
+        let mut substitutions = self.html.substitutions(values);
+        substitutions.extend(self.text.substitutions(values));
+        substitutions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::newsletter_template::{
+        ContentFormat, NewsletterTemplate, TemplateValues, TemplateVariable,
+    };
+    use chrono::TimeZone;
+    use claim::assert_err;
+
+    fn values() -> TemplateValues {
+        TemplateValues {
+            name: "Ursula & co".into(),
+            unsubscribe_url: "https://example.com/unsubscribe?token=abc".into(),
+            preferences_url: "https://example.com/preferences?token=abc".into(),
+            confirmed_at: chrono::Utc.with_ymd_and_hms(2023, 1, 5, 10, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn known_variables_are_rendered_with_or_without_inner_whitespace() {
+        let template = NewsletterTemplate::parse(
+            "Hi {{name}}, since {{ confirmed_at }}: {{  unsubscribe_url }}",
+            ContentFormat::Text,
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render(&values()),
+            "Hi Ursula & co, since January 5, 2023: https://example.com/unsubscribe?token=abc"
+        );
+    }
+
+    #[test]
+    fn html_templates_escape_the_values() {
+        let template =
+            NewsletterTemplate::parse("<p>Hi {{ name }}</p>", ContentFormat::Html).unwrap();
+
+        assert_eq!(template.render(&values()), "<p>Hi Ursula &amp; co</p>");
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        let error =
+            NewsletterTemplate::parse("Hi {{ first_name }}", ContentFormat::Text).unwrap_err();
+
+        assert!(error.contains("`first_name`"));
+    }
+
+    #[test]
+    fn unclosed_variables_are_rejected() {
+        assert_err!(NewsletterTemplate::parse("Hi {{ name", ContentFormat::Text));
+    }
+
+    #[test]
+    fn doubled_braces_stand_for_literal_braces() {
+        let template =
+            NewsletterTemplate::parse("{{{{ name }} is {{ name }}", ContentFormat::Text).unwrap();
+
+        assert_eq!(template.render(&values()), "{{ name }} is Ursula & co");
+    }
+
+    #[test]
+    fn lenient_parsing_keeps_unknown_variables_as_text() {
+        let template = NewsletterTemplate::parse_lenient(
+            "Hi {{ first_name }} {{ name }}, {{ unclosed",
+            ContentFormat::Text,
+        );
+
+        assert_eq!(
+            template.render(&values()),
+            "Hi {{ first_name }} Ursula & co, {{ unclosed"
+        );
+    }
+
+    #[test]
+    fn content_without_variables_is_left_untouched() {
+        let content = "Plain content, with a } brace";
+        let template = NewsletterTemplate::parse(content, ContentFormat::Text).unwrap();
+
+        assert!(template.variables().is_empty());
+        assert_eq!(template.render(&values()), content);
+    }
+
+    #[test]
+    fn placeholders_match_the_substitutions() {
+        let template =
+            NewsletterTemplate::parse("<p>{{ name }} {{name}}</p>", ContentFormat::Html).unwrap();
+
+        assert_eq!(template.variables(), vec![TemplateVariable::Name]);
+        let rendered = template.substitutions(&values()).into_iter().fold(
+            template.with_placeholders(),
+            |rendered, (placeholder, value)| rendered.replace(&placeholder, &value),
+        );
+        assert_eq!(rendered, template.render(&values()));
+    }
+}
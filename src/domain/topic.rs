@@ -0,0 +1,7 @@
+// A subject newsletter issues can be filed under, so subscribers can pick the
+// ones they care about.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Topic {
+    pub id: String,
+    pub name: String,
+}
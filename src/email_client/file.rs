@@ -0,0 +1,90 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::smtp::build_message;
+use crate::email_client::{EmailSender, SendEmailError, SentEmail};
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+use std::path::Path;
+
+pub struct FileEmailClient {
+    transport: AsyncFileTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl FileEmailClient {
+    pub fn new(output_directory: &Path, sender: SubscriberEmail) -> Result<Self, anyhow::Error> {
+        std::fs::create_dir_all(output_directory)?;
+        let transport = AsyncFileTransport::<Tokio1Executor>::new(output_directory);
+        Ok(Self { transport, sender })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FileEmailClient {
+    fn sender(&self) -> &SubscriberEmail {
+        &self.sender
+    }
+
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<SentEmail, SendEmailError> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )
+        .map_err(SendEmailError::Permanent)?;
+        let id = self
+            .transport
+            .send(message)
+            .await
+            .map_err(SendEmailError::permanent)?;
+        tracing::info!(email_id = %id, "Wrote email to the output directory");
+        Ok(SentEmail {
+            message_id: Some(id),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, FileEmailClient};
+    use claim::assert_ok;
+
+    #[tokio::test]
+    async fn send_email_writes_the_message_to_the_output_directory() {
+        let output_directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = FileEmailClient::new(
+            &output_directory,
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+        )
+        .unwrap();
+
+        let outcome = email_client
+            .send_email(
+                &SubscriberEmail::parse("recipient@example.com".into()).unwrap(),
+                "Subject line",
+                "<p>HTML body</p>",
+                "Plain text body",
+            )
+            .await;
+
+        assert_ok!(outcome);
+        let files: Vec<_> = std::fs::read_dir(&output_directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let written = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(written.contains("To: recipient@example.com"));
+        assert!(written.contains("Plain text body"));
+        std::fs::remove_dir_all(output_directory).unwrap();
+    }
+}
@@ -0,0 +1,883 @@
+mod file;
+mod postmark;
+mod rate_limit;
+mod retry;
+mod smtp;
+mod stdout;
+
+use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+use reqwest::{Client, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+pub use file::FileEmailClient;
+pub use postmark::PostmarkEmailClient;
+pub use rate_limit::RateLimitedEmailSender;
+pub use retry::RetryPolicy;
+pub use smtp::SmtpEmailClient;
+pub use stdout::StdoutEmailClient;
+
+#[derive(thiserror::Error)]
+pub enum SendEmailError {
+    #[error("A transient error was encountered while sending an email")]
+    Transient {
+        #[source]
+        source: anyhow::Error,
+        retry_after: Option<Duration>,
+    },
+    #[error("A permanent error was encountered while sending an email")]
+    Permanent(#[source] anyhow::Error),
+}
+
+impl SendEmailError {
+    pub fn transient(e: impl Into<anyhow::Error>) -> Self {
+        SendEmailError::Transient {
+            source: e.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn permanent(e: impl Into<anyhow::Error>) -> Self {
+        SendEmailError::Permanent(e.into())
+    }
+
+    pub fn is_transient(&self) -> bool {
+        matches!(self, SendEmailError::Transient { .. })
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            SendEmailError::Transient { retry_after, .. } => *retry_after,
+            SendEmailError::Permanent(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for SendEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<reqwest::Error> for SendEmailError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_builder() {
+            SendEmailError::permanent(e)
+        } else {
+            SendEmailError::transient(e)
+        }
+    }
+}
+
+// Rate limiting and server-side failures are worth retrying, every other
+// rejection will keep failing no matter how often we try.
+fn check_response_status(response: reqwest::Response) -> Result<reqwest::Response, SendEmailError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let error = anyhow::anyhow!("The email provider answered with {}", status);
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        Err(SendEmailError::Transient {
+            source: error,
+            retry_after,
+        })
+    } else {
+        Err(SendEmailError::Permanent(error))
+    }
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let delay = date.signed_duration_since(chrono::Utc::now());
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+// What the provider told us about an accepted email.
+#[derive(Debug, Default, Clone)]
+pub struct SentEmail {
+    pub message_id: Option<String>,
+}
+
+// One recipient of a bulk send. Every `(placeholder, value)` substitution is
+// applied to the subject and both bodies for this recipient only.
+#[derive(Debug, Clone)]
+pub struct BulkRecipient {
+    pub email: SubscriberEmail,
+    pub substitutions: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl BulkRecipient {
+    fn substitute(&self, template: &str) -> String {
+        self.substitutions
+            .iter()
+            .fold(template.to_owned(), |rendered, (placeholder, value)| {
+                rendered.replace(placeholder.as_str(), value)
+            })
+    }
+}
+
+impl SendEmailError {
+    // Bulk requests fail or succeed as a whole, but callers get one outcome
+    // per recipient.
+    fn duplicate(&self) -> Self {
+        match self {
+            SendEmailError::Transient {
+                source,
+                retry_after,
+            } => SendEmailError::Transient {
+                source: anyhow::anyhow!("{:#}", source),
+                retry_after: *retry_after,
+            },
+            SendEmailError::Permanent(source) => {
+                SendEmailError::Permanent(anyhow::anyhow!("{:#}", source))
+            }
+        }
+    }
+}
+
+fn outcome_per_recipient(
+    outcome: Result<SentEmail, SendEmailError>,
+    n_recipients: usize,
+) -> Vec<Result<SentEmail, SendEmailError>> {
+    if n_recipients == 0 {
+        return Vec::new();
+    }
+    match outcome {
+        Ok(sent) => (0..n_recipients).map(|_| Ok(sent.clone())).collect(),
+        Err(e) => {
+            let mut outcomes: Vec<_> = (1..n_recipients).map(|_| Err(e.duplicate())).collect();
+            outcomes.push(Err(e));
+            outcomes
+        }
+    }
+}
+
+// Sends a bulk email one recipient at a time, for providers without a batch
+// API.
+pub async fn send_bulk_one_by_one(
+    email_client: &(impl EmailSender + ?Sized),
+    recipients: &[BulkRecipient],
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Vec<Result<SentEmail, SendEmailError>> {
+    let mut outcomes = Vec::with_capacity(recipients.len());
+    for recipient in recipients {
+        let headers: Vec<_> = recipient
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        let outcome = email_client
+            .send_email_with_headers(
+                &recipient.email,
+                &recipient.substitute(subject),
+                &recipient.substitute(html_content),
+                &recipient.substitute(text_content),
+                &headers,
+            )
+            .await;
+        outcomes.push(outcome);
+    }
+    outcomes
+}
+
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    fn sender(&self) -> &SubscriberEmail;
+
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<SentEmail, SendEmailError>;
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<SentEmail, SendEmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    // How many recipients `send_bulk` packs into a single provider request.
+    fn max_recipients_per_request(&self) -> usize {
+        1
+    }
+
+    // Returns one outcome per recipient, in the order they were given.
+    async fn send_bulk(
+        &self,
+        recipients: &[BulkRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<Result<SentEmail, SendEmailError>> {
+        send_bulk_one_by_one(self, recipients, subject, html_content, text_content).await
+    }
+}
+
+#[derive(Clone)]
+pub struct EmailClient {
+    http_client: Client,
+    base_url: String,
+    sender: SubscriberEmail,
+    authorization_token: Secret<String>,
+    max_recipients_per_request: usize,
+}
+
+impl EmailClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+        max_recipients_per_request: usize,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            base_url,
+            sender,
+            authorization_token,
+            max_recipients_per_request: max_recipients_per_request.max(1),
+        }
+    }
+
+    async fn post(&self, request_body: &SendEmailRequest<'_>) -> Result<SentEmail, SendEmailError> {
+        let url = format!("{}/mail/send", self.base_url);
+        let response = self
+            .http_client
+            .post(&url)
+            .header(
+                "Authorization",
+                "Bearer ".to_owned() + self.authorization_token.expose_secret(),
+            )
+            .json(request_body)
+            .send()
+            .await?;
+        let response = check_response_status(response)?;
+        let message_id = response
+            .headers()
+            .get("X-Message-Id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        Ok(SentEmail { message_id })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for EmailClient {
+    fn sender(&self) -> &SubscriberEmail {
+        &self.sender
+    }
+
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<SentEmail, SendEmailError> {
+        let content =
+            email_content(html_content, text_content).map_err(SendEmailError::Permanent)?;
+        let request_body = SendEmailRequest {
+            personalization: vec![SendEmailPersonalization {
+                to: vec![SendEmailKey {
+                    email: recipient.as_ref(),
+                }],
+                substitutions: BTreeMap::new(),
+                headers: BTreeMap::new(),
+            }],
+            from: SendEmailKey {
+                email: self.sender.as_ref(),
+            },
+            subject,
+            content,
+            headers: headers.iter().copied().collect(),
+        };
+        self.post(&request_body).await
+    }
+
+    fn max_recipients_per_request(&self) -> usize {
+        self.max_recipients_per_request
+    }
+
+    async fn send_bulk(
+        &self,
+        recipients: &[BulkRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<Result<SentEmail, SendEmailError>> {
+        let content = match email_content(html_content, text_content) {
+            Ok(content) => content,
+            Err(e) => {
+                return outcome_per_recipient(Err(SendEmailError::Permanent(e)), recipients.len())
+            }
+        };
+        let mut outcomes = Vec::with_capacity(recipients.len());
+        for chunk in recipients.chunks(self.max_recipients_per_request) {
+            let personalization = chunk
+                .iter()
+                .map(|recipient| SendEmailPersonalization {
+                    to: vec![SendEmailKey {
+                        email: recipient.email.as_ref(),
+                    }],
+                    substitutions: recipient
+                        .substitutions
+                        .iter()
+                        .map(|(placeholder, value)| (placeholder.as_str(), value.as_str()))
+                        .collect(),
+                    headers: recipient
+                        .headers
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.as_str()))
+                        .collect(),
+                })
+                .collect();
+            let request_body = SendEmailRequest {
+                personalization,
+                from: SendEmailKey {
+                    email: self.sender.as_ref(),
+                },
+                subject,
+                content: content.clone(),
+                headers: BTreeMap::new(),
+            };
+            let outcome = self.post(&request_body).await;
+            outcomes.extend(outcome_per_recipient(outcome, chunk.len()));
+        }
+        outcomes
+    }
+}
+
+// The provider expects `text/plain` to come before `text/html`.
+fn email_content<'a>(
+    html_content: &'a str,
+    text_content: &'a str,
+) -> Result<Vec<SendEmailContent<'a>>, anyhow::Error> {
+    let content: Vec<_> = [("text/plain", text_content), ("text/html", html_content)]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(type_, value)| SendEmailContent { type_, value })
+        .collect();
+    if content.is_empty() {
+        anyhow::bail!("An email needs a plain text or an HTML body");
+    }
+    Ok(content)
+}
+
+#[derive(serde::Serialize)]
+struct SendEmailRequest<'a> {
+    personalization: Vec<SendEmailPersonalization<'a>>,
+    from: SendEmailKey<'a>,
+    subject: &'a str,
+    content: Vec<SendEmailContent<'a>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(serde::Serialize)]
+struct SendEmailPersonalization<'a> {
+    to: Vec<SendEmailKey<'a>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    substitutions: BTreeMap<&'a str, &'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(serde::Serialize)]
+struct SendEmailKey<'a> {
+    email: &'a str,
+}
+
+#[derive(serde::Serialize, Clone)]
+struct SendEmailContent<'a> {
+    #[serde(rename = "type")]
+    type_: &'a str,
+    value: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{ContentFormat, NewsletterTemplate, SubscriberEmail, TemplateValues};
+    use crate::email_client::{
+        parse_retry_after, send_bulk_one_by_one, BulkRecipient, EmailClient, EmailSender,
+    };
+    use claim::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::Secret;
+    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("personalization").is_some()
+                    && body.get("from").is_some()
+                    && body.get("subject").is_some()
+                    && body.get("content").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    struct SendEmailHeadersMatcher;
+
+    impl wiremock::Match for SendEmailHeadersMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body["headers"]["List-Unsubscribe"] == "<https://example.com/unsubscribe>"
+                    && body["headers"]["List-Unsubscribe-Post"] == "List-Unsubscribe=One-Click"
+            } else {
+                false
+            }
+        }
+    }
+
+    struct SendEmailContentMatcher(Vec<(&'static str, &'static str)>);
+
+    impl wiremock::Match for SendEmailContentMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                let expected: Vec<_> = self
+                    .0
+                    .iter()
+                    .map(|(type_, value)| serde_json::json!({"type": type_, "value": value}))
+                    .collect();
+                body["content"] == serde_json::Value::Array(expected)
+            } else {
+                false
+            }
+        }
+    }
+
+    struct PersonalizationCountMatcher(usize);
+
+    impl wiremock::Match for PersonalizationCountMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body["personalization"].as_array().map(Vec::len) == Some(self.0)
+            } else {
+                false
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn bulk_recipient(name: &str) -> BulkRecipient {
+        BulkRecipient {
+            email: email(),
+            substitutions: vec![("{{name}}".into(), name.into())],
+            headers: vec![("X-Recipient".into(), name.into())],
+        }
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        EmailClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            2,
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("Authorization"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/mail/send"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_sends_the_custom_headers() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/mail/send"))
+            .and(method("POST"))
+            .and(SendEmailHeadersMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email_with_headers(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &[
+                    ("List-Unsubscribe", "<https://example.com/unsubscribe>"),
+                    ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+                ],
+            )
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_both_the_plain_text_and_the_html_part() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/mail/send"))
+            .and(SendEmailContentMatcher(vec![
+                ("text/plain", "Plain text body"),
+                ("text/html", "<p>HTML body</p>"),
+            ]))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), "<p>HTML body</p>", "Plain text body")
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_only_a_plain_text_part_for_text_only_emails() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/mail/send"))
+            .and(SendEmailContentMatcher(vec![(
+                "text/plain",
+                "Plain text body",
+            )]))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), "", "Plain text body")
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_only_an_html_part_for_html_only_emails() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/mail/send"))
+            .and(SendEmailContentMatcher(vec![(
+                "text/html",
+                "<p>HTML body</p>",
+            )]))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), "<p>HTML body</p>", "")
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_without_any_body() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client.send_email(&email(), &subject(), "", "").await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_the_provider_message_id() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(202).insert_header("X-Message-Id", "msg-42"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let sent = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await
+            .unwrap();
+
+        assert_eq!(sent.message_id.as_deref(), Some("msg-42"));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn a_429_is_a_transient_failure_carrying_the_retry_after_delay() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "7"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        let error = outcome.unwrap_err();
+        assert!(error.is_transient());
+        assert_eq!(error.retry_after(), Some(std::time::Duration::from_secs(7)));
+    }
+
+    #[tokio::test]
+    async fn a_400_is_a_permanent_failure() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(!outcome.unwrap_err().is_transient());
+    }
+
+    #[test]
+    fn escaped_braces_are_not_substituted() {
+        let template =
+            NewsletterTemplate::parse("{{{{name}} is {{name}}", ContentFormat::Text).unwrap();
+        let values = TemplateValues {
+            name: "Ursula".into(),
+            unsubscribe_url: "https://example.com/unsubscribe".into(),
+            preferences_url: "https://example.com/preferences".into(),
+            confirmed_at: chrono::Utc::now(),
+        };
+        let recipient = BulkRecipient {
+            email: email(),
+            substitutions: template.substitutions(&values),
+            headers: vec![],
+        };
+
+        assert_eq!(
+            recipient.substitute(&template.with_placeholders()),
+            "{{name}} is Ursula"
+        );
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_http_dates() {
+        assert_eq!(
+            parse_retry_after("120"),
+            Some(std::time::Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(std::time::Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(outcome.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn send_bulk_packs_recipients_into_a_single_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = vec![bulk_recipient("Ursula"), bulk_recipient("Ged")];
+
+        Mock::given(path("/mail/send"))
+            .and(PersonalizationCountMatcher(2))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client
+            .send_bulk(&recipients, &subject(), "<p>Hi {{name}}</p>", "Hi {{name}}")
+            .await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(Result::is_ok));
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        let personalization = &body["personalization"][1];
+        assert_eq!(
+            personalization["to"][0]["email"],
+            recipients[1].email.as_ref()
+        );
+        assert_eq!(personalization["substitutions"]["{{name}}"], "Ged");
+        assert_eq!(personalization["headers"]["X-Recipient"], "Ged");
+    }
+
+    #[tokio::test]
+    async fn send_bulk_splits_recipients_above_the_per_request_limit() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..3).map(|i| bulk_recipient(&i.to_string())).collect();
+
+        Mock::given(PersonalizationCountMatcher(2))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(PersonalizationCountMatcher(1))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client
+            .send_bulk(&recipients, &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(&outcomes[0]);
+        assert_ok!(&outcomes[1]);
+        assert!(outcomes[2].as_ref().unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn sending_one_by_one_applies_substitutions_locally() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(SendEmailContentMatcher(vec![("text/plain", "Hi Tenar")]))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = send_bulk_one_by_one(
+            &email_client,
+            &[bulk_recipient("Tenar")],
+            &subject(),
+            "",
+            "Hi {{name}}",
+        )
+        .await;
+
+        assert_ok!(&outcomes[0]);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    send_bulk_one_by_one, BulkRecipient, EmailSender, SendEmailError, SentEmail,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// A token bucket shared by every task sending through the same client, so the
+// provider's per-second quota holds no matter how many sends run concurrently.
+struct TokenBucket {
+    capacity: f64,
+    refill_per_second: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_second: u32, burst: u32) -> Self {
+        let capacity = burst.max(1) as f64;
+        Self {
+            capacity,
+            refill_per_second: per_second as f64,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Takes a token if one is available, otherwise says how long to wait for
+    // the next one.
+    fn try_acquire(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - self.tokens;
+            Err(Duration::from_secs_f64(missing / self.refill_per_second))
+        }
+    }
+}
+
+pub struct RateLimitedEmailSender {
+    inner: Arc<dyn EmailSender>,
+    bucket: Mutex<TokenBucket>,
+}
+
+impl RateLimitedEmailSender {
+    pub fn new(inner: Arc<dyn EmailSender>, per_second: u32, burst: u32) -> Self {
+        assert!(per_second > 0, "The email rate limit must be positive");
+        Self {
+            inner,
+            bucket: Mutex::new(TokenBucket::new(per_second, burst)),
+        }
+    }
+
+    async fn acquire(&self) {
+        loop {
+            let wait = match self.bucket.lock().unwrap().try_acquire() {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for RateLimitedEmailSender {
+    fn sender(&self) -> &SubscriberEmail {
+        self.inner.sender()
+    }
+
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<SentEmail, SendEmailError> {
+        self.acquire().await;
+        self.inner
+            .send_email_with_headers(recipient, subject, html_content, text_content, headers)
+            .await
+    }
+
+    fn max_recipients_per_request(&self) -> usize {
+        self.inner.max_recipients_per_request()
+    }
+
+    // The quota counts provider requests: one token per batch when the
+    // provider can take several recipients at once, one per email otherwise.
+    async fn send_bulk(
+        &self,
+        recipients: &[BulkRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<Result<SentEmail, SendEmailError>> {
+        let batch_size = self.max_recipients_per_request();
+        if batch_size <= 1 {
+            return send_bulk_one_by_one(self, recipients, subject, html_content, text_content)
+                .await;
+        }
+        let mut outcomes = Vec::with_capacity(recipients.len());
+        for chunk in recipients.chunks(batch_size) {
+            self.acquire().await;
+            let chunk_outcomes = self
+                .inner
+                .send_bulk(chunk, subject, html_content, text_content)
+                .await;
+            outcomes.extend(chunk_outcomes);
+        }
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, RateLimitedEmailSender, SendEmailError, SentEmail};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    struct NoopSender(SubscriberEmail);
+
+    #[async_trait::async_trait]
+    impl EmailSender for NoopSender {
+        fn sender(&self) -> &SubscriberEmail {
+            &self.0
+        }
+
+        async fn send_email_with_headers(
+            &self,
+            _recipient: &SubscriberEmail,
+            _subject: &str,
+            _html_content: &str,
+            _text_content: &str,
+            _headers: &[(&str, &str)],
+        ) -> Result<SentEmail, SendEmailError> {
+            Ok(SentEmail::default())
+        }
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse("ursula@example.com".into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_within_the_burst_are_not_delayed() {
+        let inner = Arc::new(NoopSender(email()));
+        let client = RateLimitedEmailSender::new(inner, 1, 5);
+
+        let started = Instant::now();
+        for _ in 0..5 {
+            client
+                .send_email(&email(), "subject", "", "text")
+                .await
+                .unwrap();
+        }
+
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn concurrent_sends_share_the_same_quota() {
+        let inner = Arc::new(NoopSender(email()));
+        let client = Arc::new(RateLimitedEmailSender::new(inner, 20, 1));
+
+        let started = Instant::now();
+        let mut handles = Vec::new();
+        for _ in 0..5 {
+            let client = client.clone();
+            handles.push(tokio::spawn(async move {
+                client
+                    .send_email(&email(), "subject", "", "text")
+                    .await
+                    .unwrap();
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        // One token up front, then one every 50ms for the other four.
+        assert!(started.elapsed() >= Duration::from_millis(190));
+    }
+}
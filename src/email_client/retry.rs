@@ -0,0 +1,96 @@
+use crate::email_client::SendEmailError;
+use rand::Rng;
+use std::time::Duration;
+
+// How the outbox and the delivery queue reschedule failed sends.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: Duration,
+}
+
+impl RetryPolicy {
+    // Only transient failures are worth another attempt.
+    pub fn should_retry(&self, error: &SendEmailError, n_attempts: u32) -> bool {
+        error.is_transient() && n_attempts < self.max_attempts
+    }
+
+    // The wait before the next attempt, after `n_attempts` failed ones. It
+    // doubles on every attempt up to `max_delay`, with a bit of jitter to spread
+    // out retries that failed together, and is never shorter than the
+    // provider's `Retry-After`.
+    pub fn delay(&self, n_attempts: u32, retry_after: Option<Duration>) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(n_attempts.saturating_sub(1)))
+            .min(self.max_delay);
+        let jitter_ms = self.jitter.as_millis() as u64;
+        let jitter = if jitter_ms > 0 {
+            Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms))
+        } else {
+            Duration::ZERO
+        };
+        let delay = backoff.saturating_add(jitter);
+        match retry_after {
+            Some(retry_after) => delay.max(retry_after),
+            None => delay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::{RetryPolicy, SendEmailError};
+    use std::time::Duration;
+
+    fn policy(jitter: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(100),
+            jitter,
+        }
+    }
+
+    #[test]
+    fn the_delay_doubles_up_to_the_maximum() {
+        let policy = policy(Duration::ZERO);
+        assert_eq!(policy.delay(1, None), Duration::from_secs(30));
+        assert_eq!(policy.delay(2, None), Duration::from_secs(60));
+        assert_eq!(policy.delay(3, None), Duration::from_secs(100));
+    }
+
+    #[test]
+    fn jitter_is_added_on_top_of_the_backoff() {
+        let policy = policy(Duration::from_secs(5));
+        for _ in 0..100 {
+            let delay = policy.delay(1, None);
+            assert!(delay >= Duration::from_secs(30) && delay <= Duration::from_secs(35));
+        }
+    }
+
+    #[test]
+    fn the_delay_is_never_shorter_than_retry_after() {
+        let policy = policy(Duration::ZERO);
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(90))),
+            Duration::from_secs(90)
+        );
+        assert_eq!(
+            policy.delay(2, Some(Duration::from_secs(10))),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn only_transient_failures_are_retried_up_to_the_maximum() {
+        let policy = policy(Duration::ZERO);
+        let transient = SendEmailError::transient(anyhow::anyhow!("Timeout"));
+        let permanent = SendEmailError::permanent(anyhow::anyhow!("Invalid recipient"));
+        assert!(policy.should_retry(&transient, 3));
+        assert!(!policy.should_retry(&transient, 4));
+        assert!(!policy.should_retry(&permanent, 1));
+    }
+}
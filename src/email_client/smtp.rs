@@ -0,0 +1,193 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailSender, SendEmailError, SentEmail};
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+pub struct SmtpEmailClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpEmailClient {
+    pub fn new(
+        connection_url: &str,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(connection_url)?
+            .timeout(Some(timeout))
+            .build();
+        Ok(Self { transport, sender })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpEmailClient {
+    fn sender(&self) -> &SubscriberEmail {
+        &self.sender
+    }
+
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<SentEmail, SendEmailError> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )
+        .map_err(SendEmailError::Permanent)?;
+        // 5xx replies will not change on a second attempt, everything else
+        // (4xx replies, connection and timeout errors) might.
+        let response = self.transport.send(message).await.map_err(|e| {
+            if e.is_permanent() {
+                SendEmailError::permanent(e)
+            } else {
+                SendEmailError::transient(e)
+            }
+        })?;
+        // Servers usually echo the queue id in their reply to DATA.
+        let message_id = response.message().next().map(str::to_owned);
+        Ok(SentEmail { message_id })
+    }
+}
+
+pub(super) fn build_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    headers: &[(&str, &str)],
+) -> Result<Message, anyhow::Error> {
+    let mut message = Message::builder()
+        .from(sender.as_ref().parse::<Mailbox>()?)
+        .to(recipient.as_ref().parse::<Mailbox>()?)
+        .subject(subject)
+        .multipart(MultiPart::alternative_plain_html(
+            text_content.to_owned(),
+            html_content.to_owned(),
+        ))?;
+    for (name, value) in headers {
+        let name = HeaderName::new_from_ascii(name.to_string())?;
+        message
+            .headers_mut()
+            .insert_raw(HeaderValue::new(name, value.to_string()));
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, SmtpEmailClient};
+    use claim::{assert_err, assert_ok};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    // A minimal SMTP server that accepts every command and hands back the
+    // DATA section of the first message it receives.
+    async fn smtp_stand_in(listener: TcpListener) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut data = String::new();
+        let mut in_data = false;
+
+        writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if in_data {
+                if line == "." {
+                    in_data = false;
+                    writer.write_all(b"250 OK\r\n").await.unwrap();
+                } else {
+                    data.push_str(&line);
+                    data.push('\n');
+                }
+                continue;
+            }
+            let command = line.to_uppercase();
+            let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                b"250 localhost\r\n"
+            } else if command.starts_with("DATA") {
+                in_data = true;
+                b"354 End data with <CR><LF>.<CR><LF>\r\n"
+            } else if command.starts_with("QUIT") {
+                writer.write_all(b"221 Bye\r\n").await.unwrap();
+                break;
+            } else {
+                b"250 OK\r\n"
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+        data
+    }
+
+    fn email(address: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(address.to_string()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_a_multipart_message_over_smtp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(smtp_stand_in(listener));
+        let email_client = SmtpEmailClient::new(
+            &format!("smtp://127.0.0.1:{}", port),
+            email("sender@example.com"),
+            std::time::Duration::from_secs(5),
+        )
+        .unwrap();
+
+        let outcome = email_client
+            .send_email_with_headers(
+                &email("recipient@example.com"),
+                "Subject line",
+                "<p>HTML body</p>",
+                "Plain text body",
+                &[("List-Unsubscribe-Post", "List-Unsubscribe=One-Click")],
+            )
+            .await;
+
+        assert_ok!(outcome);
+        let data = server.await.unwrap();
+        assert!(data.contains("To: recipient@example.com"));
+        assert!(data.contains("Subject: Subject line"));
+        assert!(data.contains("multipart/alternative"));
+        assert!(data.contains("Plain text body"));
+        assert!(data.contains("<p>HTML body</p>"));
+        assert!(data.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_is_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let email_client = SmtpEmailClient::new(
+            &format!("smtp://127.0.0.1:{}", port),
+            email("sender@example.com"),
+            std::time::Duration::from_secs(5),
+        )
+        .unwrap();
+
+        let outcome = email_client
+            .send_email(
+                &email("recipient@example.com"),
+                "Subject line",
+                "<p>HTML body</p>",
+                "Plain text body",
+            )
+            .await;
+
+        assert_err!(outcome);
+    }
+}
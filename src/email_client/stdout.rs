@@ -0,0 +1,42 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailSender, SendEmailError, SentEmail};
+use std::io::Write;
+
+pub struct StdoutEmailClient {
+    sender: SubscriberEmail,
+}
+
+impl StdoutEmailClient {
+    pub fn new(sender: SubscriberEmail) -> Self {
+        Self { sender }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for StdoutEmailClient {
+    fn sender(&self) -> &SubscriberEmail {
+        &self.sender
+    }
+
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        _html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<SentEmail, SendEmailError> {
+        let write = || -> std::io::Result<()> {
+            let mut stdout = std::io::stdout().lock();
+            writeln!(stdout, "From: {}", self.sender)?;
+            writeln!(stdout, "To: {}", recipient)?;
+            writeln!(stdout, "Subject: {}", subject)?;
+            for (name, value) in headers {
+                writeln!(stdout, "{}: {}", name, value)?;
+            }
+            writeln!(stdout, "\n{}\n", text_content)
+        };
+        write().map_err(SendEmailError::permanent)?;
+        Ok(SentEmail::default())
+    }
+}
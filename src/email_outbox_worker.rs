@@ -0,0 +1,266 @@
+use crate::configuration::Settings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailSender, RetryPolicy, SendEmailError};
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::startup::get_connection_pool;
+use crate::suppression::{is_suppressed, record_suppressed_send, suppressed_addresses, SendKind};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+pub struct OutboxEmail {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    // `Transactional` or `Digest`.
+    pub kind: SendKind,
+}
+
+#[tracing::instrument(name = "Add an email to the outbox", skip_all)]
+pub async fn enqueue_email(
+    txn: &mut Transaction<'_, Postgres>,
+    email: &OutboxEmail,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (
+            email_outbox_id,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            kind
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        email.recipient.as_ref(),
+        email.subject,
+        email.html_content,
+        email.text_content,
+        email.kind.as_str(),
+    )
+    .execute(txn)
+    .await?;
+    Ok(())
+}
+
+pub async fn run_outbox_worker_until_stopped(
+    configuration: Settings,
+    email_client: Arc<dyn EmailSender>,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let retry_policy = configuration.email_client.retry.policy();
+    worker_loop(connection_pool, email_client, retry_policy).await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    retry_policy: RetryPolicy,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_dispatch_email(&pool, email_client.as_ref(), &retry_policy).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        email_outbox_id = tracing::field::Empty,
+        recipient = tracing::field::Empty
+    ),
+    err
+)]
+// Transient failures are rescheduled according to `retry_policy`. Once it
+// gives up, the email is marked as failed and left in the table for
+// inspection.
+pub async fn try_dispatch_email(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    retry_policy: &RetryPolicy,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_email(pool).await?;
+    if task.is_none() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let (mut transaction, email) = task.unwrap();
+    Span::current()
+        .record("email_outbox_id", display(email.email_outbox_id))
+        .record("recipient", display(&email.recipient));
+
+    let suppressed =
+        suppressed_addresses(&mut transaction, std::slice::from_ref(&email.recipient)).await?;
+    if is_suppressed(&suppressed, &email.recipient) {
+        record_suppressed_send(
+            &mut transaction,
+            &email.recipient,
+            SendKind::from_outbox(&email.kind),
+        )
+        .await?;
+        delete_email(transaction, email.email_outbox_id).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+
+    let outcome = match SubscriberEmail::parse(email.recipient.clone()) {
+        Ok(recipient) => {
+            email_client
+                .send_email(
+                    &recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                )
+                .await
+        }
+        Err(e) => Err(SendEmailError::permanent(anyhow::anyhow!(e))),
+    };
+
+    match outcome {
+        Ok(_) => delete_email(transaction, email.email_outbox_id).await?,
+        Err(e) => {
+            let n_attempts = email.n_attempts + 1;
+            if retry_policy.should_retry(&e, n_attempts as u32) {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    n_attempts,
+                    "Failed to send an email from the outbox. Rescheduling.",
+                );
+                let backoff = chrono::Duration::from_std(
+                    retry_policy.delay(n_attempts as u32, e.retry_after()),
+                )?;
+                reschedule_email(transaction, email.email_outbox_id, &e, backoff).await?;
+            } else {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    n_attempts,
+                    "Failed to send an email from the outbox. Giving up.",
+                );
+                mark_email_as_failed(transaction, email.email_outbox_id, &e).await?;
+            }
+        }
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct QueuedEmail {
+    email_outbox_id: Uuid,
+    recipient: String,
+    subject: String,
+    html_content: String,
+    text_content: String,
+    kind: String,
+    n_attempts: i32,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_email(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, QueuedEmail)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let email = sqlx::query_as!(
+        QueuedEmail,
+        r#"
+        SELECT
+            email_outbox_id,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            kind,
+            n_attempts
+        FROM email_outbox
+        WHERE
+            failed_at IS NULL AND
+            execute_after <= now()
+        ORDER BY execute_after
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    Ok(email.map(|email| (transaction, email)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_email(
+    mut transaction: PgTransaction,
+    email_outbox_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM email_outbox WHERE email_outbox_id = $1",
+        email_outbox_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_email(
+    mut transaction: PgTransaction,
+    email_outbox_id: Uuid,
+    error: &SendEmailError,
+    backoff: chrono::Duration,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET
+            n_attempts = n_attempts + 1,
+            execute_after = $2,
+            last_error = $3
+        WHERE email_outbox_id = $1
+        "#,
+        email_outbox_id,
+        chrono::Utc::now() + backoff,
+        format!("{:?}", error),
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn mark_email_as_failed(
+    mut transaction: PgTransaction,
+    email_outbox_id: Uuid,
+    error: &SendEmailError,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET
+            n_attempts = n_attempts + 1,
+            last_error = $2,
+            failed_at = now()
+        WHERE email_outbox_id = $1
+        "#,
+        email_outbox_id,
+        format!("{:?}", error),
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
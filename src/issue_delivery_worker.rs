@@ -0,0 +1,590 @@
+use crate::configuration::Settings;
+use crate::domain::{IssueTemplates, SubscriberEmail, TemplateValues};
+use crate::email_client::{BulkRecipient, EmailSender, RetryPolicy, SendEmailError};
+use crate::routes::{
+    open_pixel, preferences_link, track_links, unsubscribe_link, TRACKING_TOKEN_PLACEHOLDER,
+};
+use crate::startup::get_connection_pool_with_size;
+use crate::suppression::{is_suppressed, record_suppressed_send, suppressed_addresses, SendKind};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    email_client: Arc<dyn EmailSender>,
+) -> Result<(), anyhow::Error> {
+    let concurrency = configuration.delivery.concurrency.max(1);
+    // Every worker keeps its task locked, and so holds a connection, while it
+    // sends. The pool has room for all of them plus one to spare.
+    let connection_pool =
+        get_connection_pool_with_size(&configuration.database, concurrency as u32 + 1);
+    let base_url = configuration.application.base_url;
+    let tracking_enabled = configuration.application.tracking_enabled;
+    let retry_policy = configuration.email_client.retry.policy();
+    // Every worker claims its own task with `SKIP LOCKED`, so a slow or failing
+    // send only holds up the worker handling it. The email client, and its
+    // rate limiter, is shared with the outbox worker, which keeps the combined
+    // send rate within the provider's quota.
+    let workers = (0..concurrency).map(|_| {
+        tokio::spawn(worker_loop(
+            connection_pool.clone(),
+            email_client.clone(),
+            base_url.clone(),
+            tracking_enabled,
+            retry_policy.clone(),
+        ))
+    });
+    let (outcome, _, _) = futures::future::select_all(workers).await;
+    outcome?
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    base_url: String,
+    tracking_enabled: bool,
+    retry_policy: RetryPolicy,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(
+            &pool,
+            email_client.as_ref(),
+            &base_url,
+            tracking_enabled,
+            &retry_policy,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+pub(crate) const HTML_FOOTER: &str = r#"<p><a href="{{ preferences_url }}">Update your preferences</a> or <a href="{{ unsubscribe_url }}">unsubscribe</a> from this newsletter.</p>"#;
+pub(crate) const TEXT_FOOTER: &str = "\n\nUpdate your preferences: {{ preferences_url }}\n\
+    Unsubscribe from this newsletter: {{ unsubscribe_url }}";
+
+// Keeps additions inside the document when the issue is a full HTML page.
+pub(crate) fn append_to_body(html_content: &str, addition: &str) -> String {
+    match html_content.rfind("</body>") {
+        Some(i) => format!("{}{}{}", &html_content[..i], addition, &html_content[i..]),
+        None => format!("{}{}", html_content, addition),
+    }
+}
+
+enum DeliveryOutcome {
+    Sent(Option<String>),
+    Skipped(&'static str),
+    Failed(SendEmailError),
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id = tracing::field::Empty,
+        n_tasks = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    base_url: &str,
+    tracking_enabled: bool,
+    retry_policy: &RetryPolicy,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let batch = dequeue_tasks(pool, email_client.max_recipients_per_request()).await?;
+    let (mut transaction, tasks) = match batch {
+        Some(batch) => batch,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    let issue_id = tasks[0].newsletter_issue_id;
+    Span::current()
+        .record("newsletter_issue_id", display(issue_id))
+        .record("n_tasks", tasks.len());
+
+    let issue = get_issue(&mut transaction, issue_id).await?;
+    // Templates are validated on publication. Issues published before
+    // templating existed are sent with their braces left as they are.
+    let templates = IssueTemplates::parse_lenient(
+        &append_to_body(&issue.html_content, HTML_FOOTER),
+        &format!("{}{}", issue.text_content, TEXT_FOOTER),
+    );
+    let tracking = tracking_enabled && issue.tracking_enabled;
+    let outcomes = send_issue(
+        &mut transaction,
+        email_client,
+        base_url,
+        &tasks,
+        &issue,
+        &templates,
+        tracking,
+    )
+    .await?;
+
+    for (task, outcome) in tasks.iter().zip(outcomes) {
+        record_outcome(&mut transaction, task, outcome, retry_policy).await?;
+    }
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+// Sends the issue to every task's subscriber in a single bulk send, returning
+// one outcome per task.
+async fn send_issue(
+    transaction: &mut PgTransaction,
+    email_client: &dyn EmailSender,
+    base_url: &str,
+    tasks: &[DeliveryTask],
+    issue: &NewsletterIssue,
+    templates: &IssueTemplates,
+    tracking: bool,
+) -> Result<Vec<DeliveryOutcome>, anyhow::Error> {
+    let issue_id = tasks[0].newsletter_issue_id;
+    let mut html_content = templates.html.with_placeholders();
+    if tracking {
+        let (tracked_html, links) = track_links(&html_content, base_url);
+        save_issue_links(transaction, issue_id, &links).await?;
+        html_content = append_to_body(&tracked_html, &open_pixel(base_url));
+    }
+    let emails: Vec<String> = tasks.iter().map(|t| t.subscriber_email.clone()).collect();
+    let subscribers = get_subscribers(transaction, issue_id, &emails).await?;
+    let suppressed = suppressed_addresses(transaction, &emails).await?;
+
+    let mut outcomes: Vec<Option<DeliveryOutcome>> = tasks.iter().map(|_| None).collect();
+    let mut recipients = Vec::new();
+    let mut recipient_tasks = Vec::new();
+    for (i, task) in tasks.iter().enumerate() {
+        let subscriber = subscribers.get(&task.subscriber_email);
+        match (
+            SubscriberEmail::parse(task.subscriber_email.clone()),
+            subscriber,
+        ) {
+            _ if is_suppressed(&suppressed, &task.subscriber_email) => {
+                record_suppressed_send(
+                    transaction,
+                    &task.subscriber_email,
+                    SendKind::Newsletter(issue_id),
+                )
+                .await?;
+                outcomes[i] = Some(DeliveryOutcome::Skipped("The address is suppressed"));
+            }
+            (_, Some(subscriber)) if subscriber.is_paused => {
+                outcomes[i] = Some(DeliveryOutcome::Skipped(
+                    "The subscriber has paused delivery",
+                ));
+            }
+            (_, None) => {
+                tracing::info!(
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a subscriber who is no longer on any of the issue's lists"
+                );
+                outcomes[i] = Some(DeliveryOutcome::Skipped(
+                    "The subscriber is no longer on any of the issue's lists",
+                ));
+            }
+            (Ok(email), Some(subscriber)) => {
+                let unsubscribe_link = unsubscribe_link(base_url, &subscriber.unsubscribe_token);
+                let list_unsubscribe = format!(
+                    "<mailto:{}?subject=unsubscribe:{}>, <{}>",
+                    email_client.sender(),
+                    subscriber.unsubscribe_token,
+                    unsubscribe_link
+                );
+                let values = TemplateValues {
+                    name: subscriber.name.clone(),
+                    unsubscribe_url: unsubscribe_link,
+                    preferences_url: preferences_link(base_url, &subscriber.unsubscribe_token),
+                    confirmed_at: subscriber.confirmed_at,
+                };
+                let mut substitutions = templates.substitutions(&values);
+                if tracking {
+                    substitutions.push((
+                        TRACKING_TOKEN_PLACEHOLDER.into(),
+                        subscriber.tracking_token.clone(),
+                    ));
+                }
+                recipients.push(BulkRecipient {
+                    email,
+                    substitutions,
+                    headers: vec![
+                        ("List-Unsubscribe".into(), list_unsubscribe),
+                        (
+                            "List-Unsubscribe-Post".into(),
+                            "List-Unsubscribe=One-Click".into(),
+                        ),
+                    ],
+                });
+                recipient_tasks.push(i);
+            }
+            (Err(e), _) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a confirmed subscriber. \
+                    Their stored contact details are invalid",
+                );
+                outcomes[i] = Some(DeliveryOutcome::Skipped(
+                    "The stored email address is invalid",
+                ));
+            }
+        }
+    }
+
+    if !recipients.is_empty() {
+        let sent = email_client
+            .send_bulk(
+                &recipients,
+                &issue.title,
+                &html_content,
+                &templates.text.with_placeholders(),
+            )
+            .await;
+        for ((i, outcome), recipient) in recipient_tasks.into_iter().zip(sent).zip(&recipients) {
+            outcomes[i] = Some(match outcome {
+                Ok(sent) => DeliveryOutcome::Sent(sent.message_id),
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        subscriber_email = %recipient.email,
+                        "Failed to deliver issue to a confirmed subscriber.",
+                    );
+                    DeliveryOutcome::Failed(e)
+                }
+            });
+        }
+    }
+
+    Ok(outcomes
+        .into_iter()
+        .map(|outcome| outcome.expect("Every task has an outcome"))
+        .collect())
+}
+
+async fn record_outcome(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    outcome: DeliveryOutcome,
+    retry_policy: &RetryPolicy,
+) -> Result<(), anyhow::Error> {
+    let (issue_id, email) = (task.newsletter_issue_id, task.subscriber_email.as_str());
+    match outcome {
+        DeliveryOutcome::Sent(message_id) => {
+            let update = DeliveryUpdate {
+                status: "sent",
+                attempted: true,
+                last_error: None,
+                provider_message_id: message_id,
+            };
+            update_delivery(transaction, issue_id, email, update).await?;
+            delete_task(transaction, issue_id, email).await?;
+        }
+        DeliveryOutcome::Skipped(reason) => {
+            let update = DeliveryUpdate {
+                status: "skipped",
+                attempted: false,
+                last_error: Some(reason.into()),
+                provider_message_id: None,
+            };
+            update_delivery(transaction, issue_id, email, update).await?;
+            delete_task(transaction, issue_id, email).await?;
+        }
+        DeliveryOutcome::Failed(e) if retry_policy.should_retry(&e, task.n_retries as u32 + 1) => {
+            let update = DeliveryUpdate {
+                status: "pending",
+                attempted: true,
+                last_error: Some(format!("{:?}", e)),
+                provider_message_id: None,
+            };
+            update_delivery(transaction, issue_id, email, update).await?;
+            let backoff = chrono::Duration::from_std(
+                retry_policy.delay(task.n_retries as u32 + 1, e.retry_after()),
+            )?;
+            reschedule_task(transaction, issue_id, email, backoff).await?;
+        }
+        DeliveryOutcome::Failed(e) => {
+            let update = DeliveryUpdate {
+                status: "failed",
+                attempted: true,
+                last_error: Some(format!("{:?}", e)),
+                provider_message_id: None,
+            };
+            update_delivery(transaction, issue_id, email, update).await?;
+            delete_task(transaction, issue_id, email).await?;
+        }
+    }
+    Ok(())
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i32,
+}
+
+// Claims up to `max_tasks` due deliveries, all for the same issue so they can
+// go out in a single bulk send.
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(
+    pool: &PgPool,
+    max_tasks: usize,
+) -> Result<Option<(PgTransaction, Vec<DeliveryTask>)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let first = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let first = match first {
+        Some(first) => first,
+        None => return Ok(None),
+    };
+    let others = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email <> $2 AND
+            execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT $3
+        "#,
+        first.newsletter_issue_id,
+        first.subscriber_email,
+        max_tasks.saturating_sub(1) as i64,
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    let mut tasks = vec![first];
+    tasks.extend(others);
+    Ok(Some((transaction, tasks)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+    backoff: chrono::Duration,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = $3
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email,
+        chrono::Utc::now() + backoff,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+struct DeliveryUpdate {
+    status: &'static str,
+    attempted: bool,
+    last_error: Option<String>,
+    provider_message_id: Option<String>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn update_delivery(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+    update: DeliveryUpdate,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE deliveries
+        SET
+            status = $3,
+            n_attempts = n_attempts + $4,
+            last_error = COALESCE($5, last_error),
+            provider_message_id = COALESCE($6, provider_message_id),
+            updated_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email,
+        update.status,
+        update.attempted as i32,
+        update.last_error,
+        update.provider_message_id,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+    tracking_enabled: bool,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content, tracking_enabled
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(transaction)
+    .await?;
+    Ok(issue)
+}
+
+struct Subscriber {
+    name: String,
+    unsubscribe_token: String,
+    confirmed_at: DateTime<Utc>,
+    tracking_token: String,
+    is_paused: bool,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_subscribers(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    emails: &[String],
+) -> Result<HashMap<String, Subscriber>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            s.email,
+            s.name,
+            s.unsubscribe_token,
+            COALESCE(s.confirmed_at, s.subscribed_at) as "confirmed_at!",
+            d.tracking_token,
+            COALESCE(s.paused_until > now(), false) as "is_paused!"
+        FROM subscriptions s
+        JOIN deliveries d ON
+            d.subscriber_email = s.email AND
+            d.newsletter_issue_id = $1
+        WHERE
+            s.email = ANY($2) AND
+            s.status = 'confirmed' AND
+            EXISTS (
+                SELECT 1
+                FROM newsletter_issue_lists il
+                JOIN list_subscriptions ls ON ls.list_id = il.list_id
+                WHERE
+                    il.newsletter_issue_id = $1 AND
+                    ls.subscriber_id = s.id AND
+                    ls.status = 'confirmed'
+            )
+        "#,
+        issue_id,
+        emails
+    )
+    .fetch_all(transaction)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            let subscriber = Subscriber {
+                name: r.name,
+                unsubscribe_token: r.unsubscribe_token,
+                confirmed_at: r.confirmed_at,
+                tracking_token: r.tracking_token,
+                is_paused: r.is_paused,
+            };
+            (r.email, subscriber)
+        })
+        .collect())
+}
+
+// Links are numbered the same way in every batch, so saving them again is a
+// no-op.
+#[tracing::instrument(skip_all)]
+async fn save_issue_links(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    links: &[String],
+) -> Result<(), anyhow::Error> {
+    let link_indexes: Vec<i32> = (0..links.len() as i32).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_links (newsletter_issue_id, link_index, url)
+        SELECT $1, link_index, url FROM UNNEST($2::int[], $3::text[]) as t(link_index, url)
+        ON CONFLICT DO NOTHING
+        "#,
+        issue_id,
+        &link_indexes,
+        links,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
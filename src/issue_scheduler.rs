@@ -0,0 +1,76 @@
+use crate::configuration::Settings;
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::routes::enqueue_issue_deliveries;
+use crate::startup::get_connection_pool;
+use sqlx::PgPool;
+use std::time::Duration;
+use tracing::{field::display, Span};
+
+pub async fn run_scheduler_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    scheduler_loop(
+        connection_pool,
+        configuration.application.enqueue_batch_size,
+    )
+    .await
+}
+
+async fn scheduler_loop(pool: PgPool, enqueue_batch_size: i64) -> Result<(), anyhow::Error> {
+    loop {
+        match try_publish_due_issue(&pool, enqueue_batch_size).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+// Promotes one scheduled issue whose time has come into the delivery queue.
+#[tracing::instrument(skip_all, fields(newsletter_issue_id = tracing::field::Empty), err)]
+pub async fn try_publish_due_issue(
+    pool: &PgPool,
+    enqueue_batch_size: i64,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let issue = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE
+            status = 'scheduled' AND
+            scheduled_for <= now()
+        ORDER BY scheduled_for
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let issue_id = match issue {
+        Some(issue) => issue.newsletter_issue_id,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current().record("newsletter_issue_id", display(issue_id));
+
+    enqueue_issue_deliveries(&mut transaction, issue_id, enqueue_batch_size).await?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            status = 'published',
+            published_at = now()
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
@@ -1,6 +1,16 @@
+pub mod authentication;
 pub mod configuration;
+pub mod digest_worker;
 pub mod domain;
 pub mod email_client;
+pub mod email_outbox_worker;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod lists;
+pub mod markdown;
 pub mod routes;
+pub mod sanitization;
 pub mod startup;
+pub mod suppression;
 pub mod telemetry;
@@ -0,0 +1,125 @@
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// The list every subscriber was on before there could be several, used when a
+// subscription or an issue does not name one.
+pub const DEFAULT_LIST_SLUG: &str = "newsletter";
+
+#[derive(Debug, Clone)]
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+// The lists an issue goes out to, without repetitions, in the order given.
+pub fn resolve_lists(available: &[MailingList], slugs: &[String]) -> Result<Vec<Uuid>, String> {
+    let default_slugs = [DEFAULT_LIST_SLUG.to_string()];
+    let slugs = if slugs.is_empty() {
+        &default_slugs[..]
+    } else {
+        slugs
+    };
+    let mut list_ids = Vec::new();
+    for slug in slugs {
+        let list = available
+            .iter()
+            .find(|l| l.slug == *slug)
+            .ok_or_else(|| format!("{} is not a known list", slug))?;
+        if !list_ids.contains(&list.list_id) {
+            list_ids.push(list.list_id);
+        }
+    }
+    Ok(list_ids)
+}
+
+#[tracing::instrument(name = "Get mailing lists", skip(pool))]
+pub async fn get_lists(pool: &PgPool) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT list_id, slug, name FROM lists ORDER BY slug"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get a mailing list", skip(transaction))]
+pub async fn get_list_by_slug(
+    transaction: &mut Transaction<'_, Postgres>,
+    slug: &str,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT list_id, slug, name FROM lists WHERE slug = $1",
+        slug
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
+// Replaces the lists an issue is addressed to.
+#[tracing::instrument(name = "Set the lists of an issue", skip(transaction))]
+pub async fn set_issue_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, list_id FROM UNNEST($2::uuid[]) as list_id
+        "#,
+        newsletter_issue_id,
+        list_ids,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lists::{resolve_lists, MailingList, DEFAULT_LIST_SLUG};
+    use claim::assert_err;
+    use uuid::Uuid;
+
+    fn lists() -> Vec<MailingList> {
+        [DEFAULT_LIST_SLUG, "rust"]
+            .iter()
+            .map(|slug| MailingList {
+                list_id: Uuid::new_v4(),
+                slug: slug.to_string(),
+                name: slug.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn issues_without_lists_go_to_the_default_list() {
+        let lists = lists();
+
+        assert_eq!(resolve_lists(&lists, &[]), Ok(vec![lists[0].list_id]));
+    }
+
+    #[test]
+    fn repeated_lists_are_only_kept_once() {
+        let lists = lists();
+        let slugs = ["rust".into(), DEFAULT_LIST_SLUG.into(), "rust".into()];
+
+        assert_eq!(
+            resolve_lists(&lists, &slugs),
+            Ok(vec![lists[1].list_id, lists[0].list_id])
+        );
+    }
+
+    #[test]
+    fn unknown_lists_are_rejected() {
+        assert_err!(resolve_lists(&lists(), &["python".into()]));
+    }
+}
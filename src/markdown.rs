@@ -0,0 +1,222 @@
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+
+const TITLE_PLACEHOLDER: &str = "{{ title }}";
+const CONTENT_PLACEHOLDER: &str = "{{ content }}";
+// Used instead of the configured layout for HTML bodies that are already full
+// documents.
+const BARE_LAYOUT: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{ title }}</title>
+</head>
+<body>
+{{ content }}
+</body>
+</html>"#;
+
+// The HTML document the body rendered from Markdown is embedded into.
+#[derive(Debug, Clone)]
+pub struct HtmlLayout(String);
+
+impl HtmlLayout {
+    pub fn parse(s: String) -> Result<HtmlLayout, String> {
+        if s.contains(CONTENT_PLACEHOLDER) {
+            Ok(Self(s))
+        } else {
+            Err(format!(
+                "The newsletter layout must contain a `{}` placeholder",
+                CONTENT_PLACEHOLDER
+            ))
+        }
+    }
+
+    pub fn bare() -> HtmlLayout {
+        Self(BARE_LAYOUT.into())
+    }
+
+    pub fn wrap(&self, title: &str, content: &str) -> String {
+        let mut escaped_title = String::new();
+        escape_html(&mut escaped_title, title).expect("Writing to a String cannot fail");
+        self.0
+            .replace(TITLE_PLACEHOLDER, &escaped_title)
+            .replace(CONTENT_PLACEHOLDER, content)
+    }
+}
+
+// Whether the HTML is a document of its own rather than a fragment.
+pub fn is_document(html: &str) -> bool {
+    let start = html.trim_start().to_ascii_lowercase();
+    start.starts_with("<!doctype") || start.starts_with("<html")
+}
+
+// The inside of the `<body>` of a document, or the whole of a fragment.
+pub fn body_content(html: &str) -> &str {
+    let start = html
+        .find("<body")
+        .and_then(|i| html[i..].find('>').map(|j| i + j + 1))
+        .unwrap_or(0);
+    let end = html[start..]
+        .rfind("</body>")
+        .map(|i| start + i)
+        .unwrap_or(html.len());
+    &html[start..end]
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS
+}
+
+pub fn render_html(markdown: &str) -> String {
+    let parser = Parser::new_ext(markdown, options()).map(|event| match event {
+        // Raw HTML is shown as text instead of being passed through.
+        Event::Html(html) => Event::Text(html),
+        event => event,
+    });
+    let mut html = String::new();
+    html::push_html(&mut html, parser);
+    html
+}
+
+pub fn render_text(markdown: &str) -> String {
+    let mut text = String::new();
+    // One entry per open list, holding the next number for ordered lists.
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut link_destinations: Vec<String> = Vec::new();
+    for event in Parser::new_ext(markdown, options()) {
+        match event {
+            Event::Start(Tag::List(first_number)) => {
+                end_line(&mut text);
+                lists.push(first_number);
+            }
+            Event::End(Tag::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::End(Tag::Item) => end_line(&mut text),
+            Event::Start(Tag::Link(_, destination, _))
+            | Event::Start(Tag::Image(_, destination, _)) => {
+                link_destinations.push(destination.to_string());
+            }
+            Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => {
+                if let Some(destination) = link_destinations.pop() {
+                    // Autolinks already show their destination.
+                    if !text.ends_with(&destination) {
+                        text.push_str(&format!(" ({})", destination));
+                    }
+                }
+            }
+            Event::End(Tag::Paragraph)
+            | Event::End(Tag::Heading(..))
+            | Event::End(Tag::CodeBlock(_))
+            | Event::End(Tag::BlockQuote)
+            | Event::End(Tag::Table(_)) => {
+                end_line(&mut text);
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::End(Tag::TableCell) => text.push('\t'),
+            Event::End(Tag::TableHead) | Event::End(Tag::TableRow) => {
+                text.truncate(text.trim_end_matches('\t').len());
+                text.push('\n');
+            }
+            Event::Text(content) | Event::Code(content) => text.push_str(&content),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Rule => text.push_str("---\n\n"),
+            Event::TaskListMarker(done) => text.push_str(if done { "[x] " } else { "[ ] " }),
+            _ => {}
+        }
+    }
+    text.trim_end().to_owned()
+}
+
+fn end_line(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::{body_content, is_document, render_html, render_text, HtmlLayout};
+    use claim::assert_err;
+
+    #[test]
+    fn the_body_of_a_full_document_is_extracted() {
+        let html =
+            "<html><head><title>T</title></head><body class=\"x\">\n<p>Hi</p>\n</body></html>";
+
+        assert!(is_document(html));
+        assert_eq!(body_content(html), "\n<p>Hi</p>\n");
+    }
+
+    #[test]
+    fn fragments_are_kept_whole() {
+        assert!(!is_document("<p>Hi</p>"));
+        assert_eq!(body_content("<p>Hi</p>"), "<p>Hi</p>");
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_html() {
+        let html = render_html("# Hello\n\nSome **bold** [link](https://example.com).");
+
+        assert_eq!(
+            html,
+            "<h1>Hello</h1>\n<p>Some <strong>bold</strong> \
+            <a href=\"https://example.com\">link</a>.</p>\n"
+        );
+    }
+
+    #[test]
+    fn raw_html_is_escaped() {
+        let html = render_html("Hi <script>alert(1)</script>");
+
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn plain_text_keeps_paragraphs_lists_and_link_destinations() {
+        let text = render_text(
+            "# Hello\n\nRead [the post](https://example.com/post).\n\n\
+            - first\n- second\n\n1. one\n2. two\n\nSee <https://example.com>",
+        );
+
+        assert_eq!(
+            text,
+            "Hello\n\nRead the post (https://example.com/post).\n\n\
+            - first\n- second\n\n1. one\n2. two\n\nSee https://example.com"
+        );
+    }
+
+    #[test]
+    fn the_layout_wraps_the_content_and_escapes_the_title() {
+        let layout =
+            HtmlLayout::parse("<title>{{ title }}</title><body>{{ content }}</body>".into())
+                .unwrap();
+
+        assert_eq!(
+            layout.wrap("Tips & tricks", "<p>Hi</p>"),
+            "<title>Tips &amp; tricks</title><body><p>Hi</p></body>"
+        );
+    }
+
+    #[test]
+    fn a_layout_without_a_content_placeholder_is_rejected() {
+        assert_err!(HtmlLayout::parse("<body></body>".into()));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::routes::admin::{authenticate, AdminError};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct DeliveryReport {
+    newsletter_issue_id: Uuid,
+    pending: i64,
+    sent: i64,
+    failed: i64,
+    skipped: i64,
+    failures: Vec<FailedDelivery>,
+}
+
+#[derive(serde::Serialize)]
+pub struct FailedDelivery {
+    subscriber_email: String,
+    n_attempts: i32,
+    last_error: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct RetriedDeliveries {
+    retried: u64,
+}
+
+#[tracing::instrument(name = "Fetch the delivery report of an issue", skip(pool, request))]
+pub async fn get_delivery_report(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+    ensure_issue_exists(&pool, *issue_id).await?;
+
+    let counts = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE status = 'pending') as "pending!",
+            COUNT(*) FILTER (WHERE status = 'sent') as "sent!",
+            COUNT(*) FILTER (WHERE status = 'failed') as "failed!",
+            COUNT(*) FILTER (WHERE status = 'skipped') as "skipped!"
+        FROM deliveries
+        WHERE newsletter_issue_id = $1
+        "#,
+        *issue_id
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to count deliveries")?;
+    let failures = sqlx::query_as!(
+        FailedDelivery,
+        r#"
+        SELECT subscriber_email, n_attempts, last_error, updated_at
+        FROM deliveries
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = 'failed'
+        ORDER BY subscriber_email
+        "#,
+        *issue_id
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch failed deliveries")?;
+
+    Ok(HttpResponse::Ok().json(DeliveryReport {
+        newsletter_issue_id: issue_id.into_inner(),
+        pending: counts.pending,
+        sent: counts.sent,
+        failed: counts.failed,
+        skipped: counts.skipped,
+        failures,
+    }))
+}
+
+// Puts every failed recipient of the issue back on the delivery queue.
+#[tracing::instrument(name = "Retry failed deliveries of an issue", skip(pool, request))]
+pub async fn retry_failed_deliveries(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+    ensure_issue_exists(&pool, *issue_id).await?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let retried = sqlx::query!(
+        r#"
+        UPDATE deliveries
+        SET
+            status = 'pending',
+            updated_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = 'failed'
+        "#,
+        *issue_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to reset failed deliveries")?
+    .rows_affected();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT newsletter_issue_id, subscriber_email
+        FROM deliveries
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = 'pending'
+        ON CONFLICT DO NOTHING
+        "#,
+        *issue_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enqueue the failed deliveries")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    Ok(HttpResponse::Accepted().json(RetriedDeliveries { retried }))
+}
+
+async fn ensure_issue_exists(pool: &PgPool, issue_id: Uuid) -> Result<(), AdminError> {
+    sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the issue")?
+    .ok_or(AdminError::NotFound)?;
+    Ok(())
+}
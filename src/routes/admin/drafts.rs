@@ -0,0 +1,360 @@
+use crate::domain::{IssueTemplates, TemplateValues};
+use crate::email_outbox_worker::{enqueue_email, OutboxEmail};
+use crate::lists::{get_lists, set_issue_lists};
+use crate::markdown::HtmlLayout;
+use crate::routes::admin::{authenticate, AdminError};
+use crate::routes::{preferences_link, unsubscribe_link, BodyData};
+use crate::sanitization::HtmlPolicy;
+use crate::startup::{ApplicationBaseUrl, NewsletterTopics, PreviewRecipients};
+use crate::suppression::SendKind;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleData {
+    scheduled_for: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct CreatedDraft {
+    newsletter_issue_id: Uuid,
+}
+
+#[tracing::instrument(
+    name = "Create a draft issue",
+    skip(body, pool, newsletter_layout, html_policy, topics, request)
+)]
+pub async fn create_draft(
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    newsletter_layout: web::Data<HtmlLayout>,
+    html_policy: web::Data<HtmlPolicy>,
+    topics: web::Data<NewsletterTopics>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    let content = body
+        .render(&newsletter_layout, &html_policy)
+        .map_err(AdminError::ValidationError)?;
+    body.validate_topic(&topics.0)
+        .map_err(AdminError::ValidationError)?;
+    let lists = get_lists(&pool)
+        .await
+        .context("Failed to fetch the mailing lists")?;
+    let list_ids = body
+        .validate_lists(&lists)
+        .map_err(AdminError::ValidationError)?;
+
+    let mut transaction = begin(&pool).await?;
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            markdown_content,
+            tracking_enabled,
+            topic,
+            author_id,
+            status,
+            created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, 'draft', now())
+        "#,
+        newsletter_issue_id,
+        body.title,
+        content.text,
+        content.html,
+        content.markdown,
+        body.tracking,
+        body.topic,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the draft issue")?;
+    set_issue_lists(&mut transaction, newsletter_issue_id, &list_ids)
+        .await
+        .context("Failed to store the lists of the draft issue")?;
+    commit(transaction).await?;
+
+    Ok(HttpResponse::Created().json(CreatedDraft {
+        newsletter_issue_id,
+    }))
+}
+
+#[tracing::instrument(
+    name = "Update a draft issue",
+    skip(body, pool, newsletter_layout, html_policy, topics, request)
+)]
+pub async fn update_draft(
+    issue_id: web::Path<Uuid>,
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    newsletter_layout: web::Data<HtmlLayout>,
+    html_policy: web::Data<HtmlPolicy>,
+    topics: web::Data<NewsletterTopics>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+    let content = body
+        .render(&newsletter_layout, &html_policy)
+        .map_err(AdminError::ValidationError)?;
+    body.validate_topic(&topics.0)
+        .map_err(AdminError::ValidationError)?;
+    let lists = get_lists(&pool)
+        .await
+        .context("Failed to fetch the mailing lists")?;
+    let list_ids = body
+        .validate_lists(&lists)
+        .map_err(AdminError::ValidationError)?;
+
+    let mut transaction = begin(&pool).await?;
+    let status = lock_issue(&mut transaction, *issue_id).await?;
+    require_status(&status, &["draft"])?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            text_content = $3,
+            html_content = $4,
+            markdown_content = $5,
+            tracking_enabled = $6,
+            topic = $7
+        WHERE newsletter_issue_id = $1
+        "#,
+        *issue_id,
+        body.title,
+        content.text,
+        content.html,
+        content.markdown,
+        body.tracking,
+        body.topic,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the draft issue")?;
+    set_issue_lists(&mut transaction, *issue_id, &list_ids)
+        .await
+        .context("Failed to store the lists of the draft issue")?;
+    commit(transaction).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Delete a draft issue", skip(pool, request))]
+pub async fn delete_draft(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+
+    let mut transaction = begin(&pool).await?;
+    let status = lock_issue(&mut transaction, *issue_id).await?;
+    require_status(&status, &["draft"])?;
+    sqlx::query!(
+        "DELETE FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        *issue_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the draft issue")?;
+    commit(transaction).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(
+    name = "Send a preview of an issue",
+    skip(pool, preview_recipients, base_url, request)
+)]
+pub async fn preview_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    preview_recipients: web::Data<PreviewRecipients>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+    if preview_recipients.0.is_empty() {
+        return Err(AdminError::ValidationError(
+            "No preview recipients are configured".into(),
+        ));
+    }
+
+    let mut transaction = begin(&pool).await?;
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, text_content, html_content, status
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        *issue_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch the issue")?
+    .ok_or(AdminError::NotFound)?;
+    require_status(&issue.status, &["draft", "scheduled"])?;
+    let templates = IssueTemplates::parse(&issue.html_content, &issue.text_content)
+        .map_err(AdminError::ValidationError)?;
+    // Previews go to the team, not to subscribers, so they are rendered with
+    // placeholder values.
+    let values = TemplateValues {
+        name: "Preview Recipient".into(),
+        unsubscribe_url: unsubscribe_link(&base_url.0, "preview"),
+        preferences_url: preferences_link(&base_url.0, "preview"),
+        confirmed_at: Utc::now(),
+    };
+
+    for recipient in &preview_recipients.0 {
+        let email = OutboxEmail {
+            recipient: recipient.clone(),
+            subject: format!("[Preview] {}", issue.title),
+            html_content: templates.html.render(&values),
+            text_content: templates.text.render(&values),
+            kind: SendKind::Transactional,
+        };
+        enqueue_email(&mut transaction, &email)
+            .await
+            .context("Failed to enqueue a preview email")?;
+    }
+    commit(transaction).await?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+// Scheduling an already scheduled issue moves it to the new time.
+#[tracing::instrument(name = "Schedule an issue", skip(body, pool, request))]
+pub async fn schedule_issue(
+    issue_id: web::Path<Uuid>,
+    body: web::Json<ScheduleData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+    if body.scheduled_for <= Utc::now() {
+        return Err(AdminError::ValidationError(
+            "Issues can only be scheduled in the future".into(),
+        ));
+    }
+
+    let mut transaction = begin(&pool).await?;
+    let status = lock_issue(&mut transaction, *issue_id).await?;
+    require_status(&status, &["draft", "scheduled"])?;
+    let issue = sqlx::query!(
+        r#"
+        SELECT text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        *issue_id
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to fetch the issue")?;
+    IssueTemplates::parse(&issue.html_content, &issue.text_content)
+        .map_err(AdminError::ValidationError)?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            status = 'scheduled',
+            scheduled_for = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        *issue_id,
+        body.scheduled_for,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to schedule the issue")?;
+    commit(transaction).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// Cancelling a scheduled issue turns it back into an editable draft.
+#[tracing::instrument(name = "Cancel a scheduled issue", skip(pool, request))]
+pub async fn cancel_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+
+    let mut transaction = begin(&pool).await?;
+    let status = lock_issue(&mut transaction, *issue_id).await?;
+    require_status(&status, &["scheduled"])?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            status = 'draft',
+            scheduled_for = NULL
+        WHERE newsletter_issue_id = $1
+        "#,
+        *issue_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to cancel the scheduled issue")?;
+    commit(transaction).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn begin(pool: &PgPool) -> Result<Transaction<'static, Postgres>, AdminError> {
+    let transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    Ok(transaction)
+}
+
+async fn commit(transaction: Transaction<'static, Postgres>) -> Result<(), AdminError> {
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Lock a newsletter issue", skip(txn))]
+async fn lock_issue(
+    txn: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<String, AdminError> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT status
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        issue_id
+    )
+    .fetch_optional(txn)
+    .await
+    .context("Failed to fetch the issue")?
+    .ok_or(AdminError::NotFound)?;
+    Ok(issue.status)
+}
+
+fn require_status(status: &str, allowed: &[&str]) -> Result<(), AdminError> {
+    if allowed.contains(&status) {
+        Ok(())
+    } else {
+        Err(AdminError::Conflict(format!(
+            "The operation is not allowed on a {} issue",
+            status
+        )))
+    }
+}
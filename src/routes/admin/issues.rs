@@ -0,0 +1,238 @@
+use crate::routes::admin::{authenticate, AdminError};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(serde::Deserialize)]
+pub struct Pagination {
+    page: Option<i64>,
+    page_size: Option<i64>,
+}
+
+impl Pagination {
+    pub(super) fn validate(&self) -> Result<(i64, i64), AdminError> {
+        let page = self.page.unwrap_or(1);
+        let page_size = self.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+        if page < 1 {
+            return Err(AdminError::ValidationError(
+                "`page` must be greater than zero".into(),
+            ));
+        }
+        if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+            return Err(AdminError::ValidationError(format!(
+                "`page_size` must be between 1 and {}",
+                MAX_PAGE_SIZE
+            )));
+        }
+        Ok((page, page_size))
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    author: Option<String>,
+    status: String,
+    created_at: DateTime<Utc>,
+    scheduled_for: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct IssuePage {
+    issues: Vec<IssueSummary>,
+    page: i64,
+    page_size: i64,
+    total: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueDetails {
+    newsletter_issue_id: Uuid,
+    title: String,
+    author: Option<String>,
+    status: String,
+    html_content: String,
+    text_content: String,
+    markdown_content: Option<String>,
+    topic: Option<String>,
+    lists: Vec<String>,
+    created_at: DateTime<Utc>,
+    scheduled_for: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueStats {
+    newsletter_issue_id: Uuid,
+    recipients: i64,
+    pending: i64,
+    processed: i64,
+    tracking_enabled: bool,
+    delivered: i64,
+    unique_opens: i64,
+    unique_clicks: i64,
+    open_rate: f64,
+    click_rate: f64,
+}
+
+#[tracing::instrument(name = "List newsletter issues", skip(pool, request, pagination))]
+pub async fn list_issues(
+    pagination: web::Query<Pagination>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+    let (page, page_size) = pagination.validate()?;
+
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            u.username as "author?",
+            i.status,
+            i.created_at,
+            i.scheduled_for,
+            i.published_at
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        ORDER BY i.created_at DESC, i.newsletter_issue_id
+        LIMIT $1 OFFSET $2
+        "#,
+        page_size,
+        (page - 1) * page_size,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch newsletter issues")?;
+    let total = sqlx::query!(r#"SELECT COUNT(*) as "total!" FROM newsletter_issues"#)
+        .fetch_one(pool.get_ref())
+        .await
+        .context("Failed to count newsletter issues")?
+        .total;
+
+    Ok(HttpResponse::Ok().json(IssuePage {
+        issues,
+        page,
+        page_size,
+        total,
+    }))
+}
+
+#[tracing::instrument(name = "Fetch a newsletter issue", skip(pool, request))]
+pub async fn get_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+
+    let issue = sqlx::query_as!(
+        IssueDetails,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            u.username as "author?",
+            i.status,
+            i.html_content,
+            i.text_content,
+            i.markdown_content,
+            i.topic,
+            ARRAY(
+                SELECT l.slug
+                FROM newsletter_issue_lists il
+                JOIN lists l ON l.list_id = il.list_id
+                WHERE il.newsletter_issue_id = i.newsletter_issue_id
+                ORDER BY l.slug
+            ) as "lists!",
+            i.created_at,
+            i.scheduled_for,
+            i.published_at
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        issue_id.into_inner(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the newsletter issue")?
+    .ok_or(AdminError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+#[tracing::instrument(name = "Fetch delivery statistics for an issue", skip(pool, request))]
+pub async fn get_issue_stats(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+
+    let stats = sqlx::query!(
+        r#"
+        SELECT
+            i.n_recipients,
+            i.tracking_enabled,
+            (
+                SELECT COUNT(*)
+                FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            ) as "pending!",
+            (
+                SELECT COUNT(*)
+                FROM deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id AND d.status = 'sent'
+            ) as "delivered!",
+            (
+                SELECT COUNT(DISTINCT e.subscriber_email)
+                FROM tracking_events e
+                WHERE e.newsletter_issue_id = i.newsletter_issue_id AND e.kind = 'open'
+            ) as "unique_opens!",
+            (
+                SELECT COUNT(DISTINCT e.subscriber_email)
+                FROM tracking_events e
+                WHERE e.newsletter_issue_id = i.newsletter_issue_id AND e.kind = 'click'
+            ) as "unique_clicks!"
+        FROM newsletter_issues i
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        *issue_id,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch delivery statistics")?
+    .ok_or(AdminError::NotFound)?;
+
+    let recipients = stats.n_recipients as i64;
+    Ok(HttpResponse::Ok().json(IssueStats {
+        newsletter_issue_id: issue_id.into_inner(),
+        recipients,
+        pending: stats.pending,
+        processed: recipients - stats.pending,
+        tracking_enabled: stats.tracking_enabled,
+        delivered: stats.delivered,
+        unique_opens: stats.unique_opens,
+        unique_clicks: stats.unique_clicks,
+        open_rate: rate(stats.unique_opens, stats.delivered),
+        click_rate: rate(stats.unique_clicks, stats.delivered),
+    }))
+}
+
+fn rate(count: i64, delivered: i64) -> f64 {
+    if delivered == 0 {
+        0.0
+    } else {
+        count as f64 / delivered as f64
+    }
+}
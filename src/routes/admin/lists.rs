@@ -0,0 +1,159 @@
+use crate::routes::admin::{authenticate, AdminError};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_SLUG_LENGTH: usize = 64;
+
+#[derive(serde::Deserialize)]
+pub struct ListData {
+    slug: String,
+    name: String,
+}
+
+impl ListData {
+    // Slugs end up in subscription forms and links, so they are kept to
+    // lowercase letters, digits and dashes.
+    fn validate(&self) -> Result<(), String> {
+        let is_valid_slug = !self.slug.is_empty()
+            && self.slug.len() <= MAX_SLUG_LENGTH
+            && !self.slug.starts_with('-')
+            && self
+                .slug
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !is_valid_slug {
+            return Err(format!(
+                "{} is not a valid slug. Use up to {} lowercase letters, digits and dashes",
+                self.slug, MAX_SLUG_LENGTH
+            ));
+        }
+        if self.name.trim().is_empty() {
+            return Err("A name is required".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct ListSummary {
+    list_id: Uuid,
+    slug: String,
+    name: String,
+    created_at: DateTime<Utc>,
+    confirmed_subscribers: i64,
+    pending_subscribers: i64,
+}
+
+#[derive(serde::Serialize)]
+struct ListsResponse {
+    lists: Vec<ListSummary>,
+}
+
+#[derive(serde::Serialize)]
+struct CreatedList {
+    list_id: Uuid,
+}
+
+#[tracing::instrument(name = "List mailing lists", skip(pool, request))]
+pub async fn list_lists(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+
+    let lists = sqlx::query_as!(
+        ListSummary,
+        r#"
+        SELECT
+            l.list_id,
+            l.slug,
+            l.name,
+            l.created_at,
+            COUNT(*) FILTER (WHERE ls.status = 'confirmed') as "confirmed_subscribers!",
+            COUNT(*) FILTER (WHERE ls.status = 'pending_confirmation') as "pending_subscribers!"
+        FROM lists l
+        LEFT JOIN list_subscriptions ls ON ls.list_id = l.list_id
+        GROUP BY l.list_id
+        ORDER BY l.slug
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the mailing lists")?;
+
+    Ok(HttpResponse::Ok().json(ListsResponse { lists }))
+}
+
+#[tracing::instrument(name = "Create a mailing list", skip(body, pool, request))]
+pub async fn create_list(
+    body: web::Json<ListData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+    body.validate().map_err(AdminError::ValidationError)?;
+
+    let list_id = Uuid::new_v4();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, created_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        list_id,
+        body.slug,
+        body.name.trim(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the mailing list")?;
+    if result.rows_affected() == 0 {
+        return Err(AdminError::Conflict(format!(
+            "There already is a list with the slug {}",
+            body.slug
+        )));
+    }
+
+    Ok(HttpResponse::Created().json(CreatedList { list_id }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::admin::lists::ListData;
+    use claim::{assert_err, assert_ok};
+
+    fn data(slug: &str, name: &str) -> ListData {
+        ListData {
+            slug: slug.into(),
+            name: name.into(),
+        }
+    }
+
+    #[test]
+    fn lowercase_slugs_with_dashes_are_accepted() {
+        assert_ok!(data("rust-weekly-2", "Rust weekly").validate());
+    }
+
+    #[test]
+    fn invalid_slugs_are_rejected() {
+        let too_long = "a".repeat(65);
+        for slug in [
+            "",
+            "Rust",
+            "rust weekly",
+            "-rust",
+            "rüst",
+            too_long.as_str(),
+        ] {
+            assert_err!(data(slug, "Rust weekly").validate());
+        }
+    }
+
+    #[test]
+    fn a_name_is_required() {
+        assert_err!(data("rust", " ").validate());
+    }
+}
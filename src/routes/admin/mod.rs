@@ -0,0 +1,78 @@
+mod deliveries;
+mod drafts;
+mod issues;
+mod lists;
+mod suppressions;
+
+pub use deliveries::*;
+pub use drafts::*;
+pub use issues::*;
+pub use lists::*;
+pub use suppressions::*;
+
+use crate::authentication::{basic_authentication, validate_credentials, AuthError};
+use crate::routes::error_chain_fmt;
+use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The requested resource does not exist")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AdminError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AdminError::UnexpectedError(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            AdminError::ValidationError(message) => {
+                HttpResponse::BadRequest().body(message.clone())
+            }
+            AdminError::NotFound => HttpResponse::new(StatusCode::NOT_FOUND),
+            AdminError::Conflict(message) => HttpResponse::Conflict().body(message.clone()),
+            AdminError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="admin""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Authenticate an admin request",
+    skip(headers, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+async fn authenticate(headers: &HeaderMap, pool: &PgPool) -> Result<Uuid, AdminError> {
+    let credentials = basic_authentication(headers).map_err(AdminError::AuthError)?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let user_id = validate_credentials(credentials, pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => AdminError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => AdminError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    Ok(user_id)
+}
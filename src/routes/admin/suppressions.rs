@@ -0,0 +1,258 @@
+use crate::domain::SubscriberEmail;
+use crate::routes::admin::{authenticate, AdminError, Pagination};
+use crate::suppression::{insert_suppression, SuppressionTarget};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionData {
+    address: Option<String>,
+    domain: Option<String>,
+    reason: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl SuppressionData {
+    fn validate(&self) -> Result<SuppressionTarget, String> {
+        if self.reason.trim().is_empty() {
+            return Err("A reason is required".into());
+        }
+        if matches!(self.expires_at, Some(expires_at) if expires_at <= Utc::now()) {
+            return Err("`expires_at` must be in the future".into());
+        }
+        match (&self.address, &self.domain) {
+            (Some(address), None) => {
+                let address = SubscriberEmail::parse(address.clone())?;
+                Ok(SuppressionTarget::Address(address.as_ref().to_lowercase()))
+            }
+            (None, Some(domain)) => {
+                let domain = domain.trim().trim_start_matches('@').to_lowercase();
+                let is_valid = domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+                    && !domain.contains(|c: char| c == '@' || c.is_whitespace());
+                if is_valid {
+                    Ok(SuppressionTarget::Domain(domain))
+                } else {
+                    Err(format!("{} is not a valid domain", domain))
+                }
+            }
+            _ => Err("Exactly one of `address` and `domain` is required".into()),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Suppression {
+    suppression_id: Uuid,
+    address: Option<String>,
+    domain: Option<String>,
+    reason: String,
+    source: String,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    active: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct SuppressionPage {
+    suppressions: Vec<Suppression>,
+    page: i64,
+    page_size: i64,
+    total: i64,
+}
+
+#[derive(serde::Serialize)]
+struct CreatedSuppression {
+    suppression_id: Uuid,
+}
+
+#[derive(serde::Serialize)]
+pub struct SuppressionStats {
+    active_suppressions: i64,
+    suppressed_sends: i64,
+    suppressed_transactional_sends: i64,
+    suppressed_newsletter_sends: i64,
+    suppressed_digest_sends: i64,
+}
+
+#[tracing::instrument(name = "List suppressions", skip(pool, request, pagination))]
+pub async fn list_suppressions(
+    pagination: web::Query<Pagination>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+    let (page, page_size) = pagination.validate()?;
+
+    let suppressions = sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT
+            suppression_id,
+            address,
+            domain,
+            reason,
+            source,
+            created_at,
+            expires_at,
+            (expires_at IS NULL OR expires_at > now()) as "active!"
+        FROM suppressions
+        ORDER BY created_at DESC, suppression_id
+        LIMIT $1 OFFSET $2
+        "#,
+        page_size,
+        (page - 1) * page_size,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch suppressions")?;
+    let total = sqlx::query!(r#"SELECT COUNT(*) as "total!" FROM suppressions"#)
+        .fetch_one(pool.get_ref())
+        .await
+        .context("Failed to count suppressions")?
+        .total;
+
+    Ok(HttpResponse::Ok().json(SuppressionPage {
+        suppressions,
+        page,
+        page_size,
+        total,
+    }))
+}
+
+#[tracing::instrument(name = "Add a suppression", skip(body, pool, request))]
+pub async fn add_suppression(
+    body: web::Json<SuppressionData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+    let target = body.validate().map_err(AdminError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let suppression_id = insert_suppression(
+        &mut transaction,
+        &target,
+        body.reason.trim(),
+        "admin",
+        body.expires_at,
+    )
+    .await
+    .context("Failed to store the suppression")?
+    .ok_or_else(|| AdminError::Conflict("The address or domain is already suppressed".into()))?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    Ok(HttpResponse::Created().json(CreatedSuppression { suppression_id }))
+}
+
+#[tracing::instrument(name = "Remove a suppression", skip(pool, request))]
+pub async fn remove_suppression(
+    suppression_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+
+    let result = sqlx::query!(
+        "DELETE FROM suppressions WHERE suppression_id = $1",
+        *suppression_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to delete the suppression")?;
+    if result.rows_affected() == 0 {
+        return Err(AdminError::NotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(name = "Fetch suppression statistics", skip(pool, request))]
+pub async fn get_suppression_stats(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(request.headers(), &pool).await?;
+
+    let stats = sqlx::query_as!(
+        SuppressionStats,
+        r#"
+        SELECT
+            (
+                SELECT COUNT(*)
+                FROM suppressions
+                WHERE expires_at IS NULL OR expires_at > now()
+            ) as "active_suppressions!",
+            COUNT(*) as "suppressed_sends!",
+            COUNT(*) FILTER (WHERE kind = 'transactional') as "suppressed_transactional_sends!",
+            COUNT(*) FILTER (WHERE kind = 'newsletter') as "suppressed_newsletter_sends!",
+            COUNT(*) FILTER (WHERE kind = 'digest') as "suppressed_digest_sends!"
+        FROM suppressed_sends
+        "#
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to compute suppression statistics")?;
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::admin::suppressions::SuppressionData;
+    use crate::suppression::SuppressionTarget;
+    use claim::assert_err;
+
+    fn data(address: Option<&str>, domain: Option<&str>) -> SuppressionData {
+        SuppressionData {
+            address: address.map(Into::into),
+            domain: domain.map(Into::into),
+            reason: "Requested by the recipient".into(),
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn addresses_and_domains_are_normalized() {
+        assert_eq!(
+            data(Some("Ursula@Example.com"), None).validate(),
+            Ok(SuppressionTarget::Address("ursula@example.com".into()))
+        );
+        assert_eq!(
+            data(None, Some("@Example.COM")).validate(),
+            Ok(SuppressionTarget::Domain("example.com".into()))
+        );
+    }
+
+    #[test]
+    fn exactly_one_target_is_required() {
+        assert_err!(data(None, None).validate());
+        assert_err!(data(Some("ursula@example.com"), Some("example.com")).validate());
+    }
+
+    #[test]
+    fn invalid_targets_are_rejected() {
+        assert_err!(data(Some("not-an-address"), None).validate());
+        for domain in ["localhost", "ursula@example.com", ".com", "exa mple.com"] {
+            assert_err!(data(None, Some(domain)).validate());
+        }
+    }
+
+    #[test]
+    fn expiry_dates_in_the_past_are_rejected() {
+        let mut data = data(Some("ursula@example.com"), None);
+        data.expires_at = Some(chrono::Utc::now() - chrono::Duration::days(1));
+
+        assert_err!(data.validate());
+    }
+}
@@ -0,0 +1,303 @@
+use crate::routes::error_chain_fmt;
+use crate::suppression::{insert_suppression, SuppressionTarget};
+use actix_web::http::header::HeaderMap;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+const SIGNATURE_HEADER: &str = "X-Twilio-Email-Event-Webhook-Signature";
+const TIMESTAMP_HEADER: &str = "X-Twilio-Email-Event-Webhook-Timestamp";
+// Older signatures are rejected, so captured requests cannot be replayed.
+const MAX_SIGNATURE_AGE_SECONDS: i64 = 300;
+
+pub struct WebhookSigningKey(pub Secret<String>);
+
+#[derive(serde::Deserialize, Debug)]
+pub struct EmailEvent {
+    email: String,
+    event: String,
+    timestamp: i64,
+    // Tells hard bounces (`bounce`) apart from temporary ones (`blocked`).
+    #[serde(rename = "type")]
+    bounce_type: Option<String>,
+    reason: Option<String>,
+    sg_event_id: Option<String>,
+}
+
+impl EmailEvent {
+    // The status subscribers are moved to when the event is reported for
+    // their address, if any.
+    fn subscriber_status(&self) -> Option<&'static str> {
+        match (self.event.as_str(), self.bounce_type.as_deref()) {
+            ("bounce", Some("blocked")) => None,
+            ("bounce", _) => Some("bounced"),
+            ("spamreport", _) => Some("complained"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum EmailEventsError {
+    #[error("The webhook signature is missing or invalid")]
+    InvalidSignature,
+    #[error("The event batch could not be parsed")]
+    InvalidPayload(#[source] serde_json::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for EmailEventsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for EmailEventsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            EmailEventsError::InvalidSignature => StatusCode::UNAUTHORIZED,
+            EmailEventsError::InvalidPayload(_) => StatusCode::BAD_REQUEST,
+            EmailEventsError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// The signature is the base64-encoded HMAC-SHA256 of the timestamp header
+// followed by the raw request body.
+pub fn sign_email_events(signing_key: &Secret<String>, timestamp: &str, body: &[u8]) -> String {
+    base64::encode(mac(signing_key, timestamp, body).finalize().into_bytes())
+}
+
+fn mac(signing_key: &Secret<String>, timestamp: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(signing_key.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(body);
+    mac
+}
+
+fn verify_signature(
+    headers: &HeaderMap,
+    body: &[u8],
+    signing_key: &Secret<String>,
+    now: DateTime<Utc>,
+) -> Result<(), EmailEventsError> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .ok_or(EmailEventsError::InvalidSignature)
+    };
+    let timestamp = header(TIMESTAMP_HEADER)?;
+    let signature = base64::decode(header(SIGNATURE_HEADER)?)
+        .map_err(|_| EmailEventsError::InvalidSignature)?;
+    let signed_at: i64 = timestamp
+        .parse()
+        .map_err(|_| EmailEventsError::InvalidSignature)?;
+    if (now.timestamp() - signed_at).abs() > MAX_SIGNATURE_AGE_SECONDS {
+        return Err(EmailEventsError::InvalidSignature);
+    }
+    mac(signing_key, timestamp, body)
+        .verify_slice(&signature)
+        .map_err(|_| EmailEventsError::InvalidSignature)
+}
+
+#[tracing::instrument(name = "Receive email events", skip(request, body, pool, signing_key))]
+pub async fn receive_email_events(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    signing_key: web::Data<WebhookSigningKey>,
+) -> Result<HttpResponse, EmailEventsError> {
+    verify_signature(request.headers(), &body, &signing_key.0, Utc::now())?;
+    let events: Vec<EmailEvent> =
+        serde_json::from_slice(&body).map_err(EmailEventsError::InvalidPayload)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    for event in &events {
+        let is_new = record_email_event(&mut transaction, event)
+            .await
+            .context("Failed to record an email event")?;
+        if let (true, Some(status)) = (is_new, event.subscriber_status()) {
+            update_subscriber_status(&mut transaction, &event.email, status)
+                .await
+                .context("Failed to update the subscriber status")?;
+            // Also stops confirmation emails, should the address subscribe
+            // again.
+            let target = SuppressionTarget::Address(event.email.to_lowercase());
+            let reason = event.reason.as_deref().unwrap_or(status);
+            insert_suppression(&mut transaction, &target, reason, "provider", None)
+                .await
+                .context("Failed to suppress the address")?;
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record email events")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// Returns false if the provider already reported the event.
+#[tracing::instrument(name = "Record an email event", skip(transaction))]
+async fn record_email_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &EmailEvent,
+) -> Result<bool, sqlx::Error> {
+    let occurred_at = Utc
+        .timestamp_opt(event.timestamp, 0)
+        .single()
+        .unwrap_or_else(Utc::now);
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            event_id,
+            provider_event_id,
+            subscriber_email,
+            event,
+            bounce_type,
+            reason,
+            occurred_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (provider_event_id) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        event.sg_event_id,
+        event.email,
+        event.event,
+        event.bounce_type,
+        event.reason,
+        occurred_at,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// A complaint overrides any other status, a bounce overrides everything but a
+// complaint.
+#[tracing::instrument(name = "Update the subscriber status", skip(transaction))]
+async fn update_subscriber_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    status: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = $2
+        WHERE
+            email = $1 AND
+            status <> 'complained' AND
+            status <> $2
+        "#,
+        email,
+        status,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::email_events::{
+        sign_email_events, verify_signature, EmailEvent, EmailEventsError, SIGNATURE_HEADER,
+        TIMESTAMP_HEADER,
+    };
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+    use chrono::{TimeZone, Utc};
+    use secrecy::Secret;
+
+    fn event(event: &str, bounce_type: Option<&str>) -> EmailEvent {
+        EmailEvent {
+            email: "ursula@example.com".into(),
+            event: event.into(),
+            timestamp: 0,
+            bounce_type: bounce_type.map(Into::into),
+            reason: None,
+            sg_event_id: None,
+        }
+    }
+
+    fn signed_headers(key: &str, timestamp: i64, body: &[u8]) -> HeaderMap {
+        let timestamp = timestamp.to_string();
+        let signature = sign_email_events(&Secret::new(key.into()), &timestamp, body);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("x-twilio-email-event-webhook-timestamp"),
+            HeaderValue::from_str(&timestamp).unwrap(),
+        );
+        headers.insert(
+            HeaderName::from_static("x-twilio-email-event-webhook-signature"),
+            HeaderValue::from_str(&signature).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn only_hard_bounces_and_complaints_change_the_subscriber_status() {
+        assert_eq!(
+            event("bounce", Some("bounce")).subscriber_status(),
+            Some("bounced")
+        );
+        assert_eq!(event("bounce", Some("blocked")).subscriber_status(), None);
+        assert_eq!(
+            event("spamreport", None).subscriber_status(),
+            Some("complained")
+        );
+        assert_eq!(event("delivered", None).subscriber_status(), None);
+    }
+
+    #[test]
+    fn a_valid_signature_is_accepted() {
+        let now = Utc.with_ymd_and_hms(2023, 2, 26, 12, 0, 0).unwrap();
+        let headers = signed_headers("secret", now.timestamp(), b"[]");
+
+        assert!(verify_signature(&headers, b"[]", &Secret::new("secret".into()), now).is_ok());
+    }
+
+    #[test]
+    fn tampered_bodies_and_wrong_keys_are_rejected() {
+        let now = Utc.with_ymd_and_hms(2023, 2, 26, 12, 0, 0).unwrap();
+        let headers = signed_headers("secret", now.timestamp(), b"[]");
+
+        for (body, key) in [(&b"[{}]"[..], "secret"), (&b"[]"[..], "other")] {
+            assert!(matches!(
+                verify_signature(&headers, body, &Secret::new(key.into()), now),
+                Err(EmailEventsError::InvalidSignature)
+            ));
+        }
+    }
+
+    #[test]
+    fn stale_signatures_are_rejected() {
+        let now = Utc.with_ymd_and_hms(2023, 2, 26, 12, 0, 0).unwrap();
+        let headers = signed_headers("secret", now.timestamp() - 301, b"[]");
+
+        assert!(verify_signature(&headers, b"[]", &Secret::new("secret".into()), now).is_err());
+    }
+
+    #[test]
+    fn missing_headers_are_rejected() {
+        let now = Utc::now();
+        let mut headers = signed_headers("secret", now.timestamp(), b"[]");
+        headers.remove(SIGNATURE_HEADER);
+        assert!(verify_signature(&headers, b"[]", &Secret::new("secret".into()), now).is_err());
+
+        let mut headers = signed_headers("secret", now.timestamp(), b"[]");
+        headers.remove(TIMESTAMP_HEADER);
+        assert!(verify_signature(&headers, b"[]", &Secret::new("secret".into()), now).is_err());
+    }
+}
@@ -1,9 +1,19 @@
+mod admin;
+mod email_events;
 mod health_check;
 mod newsletters;
+mod preferences;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod tracking;
 
+pub use admin::*;
+pub use email_events::*;
 pub use health_check::*;
 pub use newsletters::*;
+pub use preferences::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use tracking::*;
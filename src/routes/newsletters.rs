@@ -1,30 +1,117 @@
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
-use crate::routes::error_chain_fmt;
-use actix_web::http::StatusCode;
-use actix_web::{web, HttpResponse, ResponseError};
+use crate::authentication::{basic_authentication, validate_credentials, AuthError};
+use crate::domain::{IssueTemplates, SubscriberEmail, Topic};
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::lists::{get_lists, resolve_lists, set_issue_lists, MailingList};
+use crate::markdown::{body_content, is_document, render_html, render_text, HtmlLayout};
+use crate::routes::{error_chain_fmt, Frequency};
+use crate::sanitization::HtmlPolicy;
+use crate::startup::{EnqueueBatchSize, NewsletterTopics};
+use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
-use sqlx::PgPool;
-use std::fmt::Formatter;
-
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
-}
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
-    title: String,
-    content: Content,
+    pub title: String,
+    pub content: Content,
+    // Opt-in to open and click tracking for this issue.
+    #[serde(default)]
+    pub tracking: bool,
+    // Only subscribers interested in the topic receive the issue.
+    pub topic: Option<String>,
+    // Slugs of the lists the issue goes out to. Empty means the default list.
+    #[serde(default)]
+    pub lists: Vec<String>,
 }
 
+// Either part can be written by hand. Missing parts are rendered from the
+// Markdown body. HTML bodies are sanitized and wrapped in the layout in both
+// cases.
 #[derive(serde::Deserialize)]
 pub struct Content {
-    html: String,
-    text: String,
+    pub markdown: Option<String>,
+    pub html: Option<String>,
+    pub text: Option<String>,
+}
+
+pub struct RenderedContent {
+    pub markdown: Option<String>,
+    pub html: String,
+    pub text: String,
+}
+
+impl BodyData {
+    pub fn validate_topic(&self, topics: &[Topic]) -> Result<(), String> {
+        match &self.topic {
+            Some(topic) if !topics.iter().any(|t| t.id == *topic) => {
+                Err(format!("{} is not a known topic", topic))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn validate_lists(&self, lists: &[MailingList]) -> Result<Vec<Uuid>, String> {
+        resolve_lists(lists, &self.lists)
+    }
+
+    pub fn render(
+        &self,
+        layout: &HtmlLayout,
+        policy: &HtmlPolicy,
+    ) -> Result<RenderedContent, String> {
+        let content = &self.content;
+        let html_body = match (&content.html, &content.markdown) {
+            (Some(html), _) => html.clone(),
+            (None, Some(markdown)) => render_html(markdown),
+            (None, None) => return Err("The content needs a Markdown or an HTML body".into()),
+        };
+        // Full documents already carry their own layout. Only their body is
+        // kept, as their head is not sanitized.
+        let html = if is_document(&html_body) {
+            let html_body = policy
+                .clean(body_content(&html_body))
+                .map_err(invalid_html)?;
+            HtmlLayout::bare().wrap(&self.title, &html_body)
+        } else {
+            let html_body = policy.clean(&html_body).map_err(invalid_html)?;
+            layout.wrap(&self.title, &html_body)
+        };
+        policy.check_size(&html).map_err(invalid_html)?;
+        let text = match (&content.text, &content.markdown) {
+            (Some(text), _) => text.clone(),
+            (None, Some(markdown)) => render_text(markdown),
+            (None, None) => return Err("The content needs a Markdown or a plain text body".into()),
+        };
+        Ok(RenderedContent {
+            markdown: content.markdown.clone(),
+            html,
+            text,
+        })
+    }
+}
+
+fn invalid_html(problems: Vec<String>) -> String {
+    let mut message = "The HTML content is invalid:".to_string();
+    for problem in problems {
+        message.push_str("\n- ");
+        message.push_str(&problem);
+    }
+    message
 }
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Invalid idempotency key")]
+    InvalidIdempotencyKey(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -36,65 +123,330 @@ impl std::fmt::Debug for PublishError {
 }
 
 impl ResponseError for PublishError {
-    fn status_code(&self) -> StatusCode {
+    fn error_response(&self) -> HttpResponse {
         match self {
-            PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PublishError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            PublishError::InvalidIdempotencyKey(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            PublishError::ValidationError(message) => {
+                HttpResponse::BadRequest().body(message.clone())
+            }
+            PublishError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
         }
     }
 }
 
-#[tracing::instrument(name = "Get confirmed subscriber", skip(pool))]
-async fn get_confirmed_subscriber(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
+struct Recipient {
+    email: String,
+    frequency: String,
+}
+
+// Confirmed subscribers who want the issue: they confirmed at least one of
+// the issue's lists, delivery is not paused and the issue's topic is one they
+// picked. Subscribers on several of the lists are only returned once.
+#[tracing::instrument(name = "Get a batch of recipients", skip(txn))]
+async fn get_recipients_after(
+    txn: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    last_email: Option<&str>,
+    batch_size: i64,
+) -> Result<Vec<Recipient>, sqlx::Error> {
+    sqlx::query_as!(
+        Recipient,
         r#"
-        SELECT email
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        SELECT s.email, s.frequency
+        FROM subscriptions s
+        JOIN newsletter_issues i ON i.newsletter_issue_id = $1
+        WHERE
+            s.status = 'confirmed' AND
+            (s.paused_until IS NULL OR s.paused_until <= now()) AND
+            (i.topic IS NULL OR cardinality(s.topics) = 0 OR i.topic = ANY(s.topics)) AND
+            EXISTS (
+                SELECT 1
+                FROM newsletter_issue_lists il
+                JOIN list_subscriptions ls ON ls.list_id = il.list_id
+                WHERE
+                    il.newsletter_issue_id = $1 AND
+                    ls.subscriber_id = s.id AND
+                    ls.status = 'confirmed'
+            ) AND
+            ($2::text IS NULL OR s.email > $2)
+        ORDER BY s.email
+        LIMIT $3
         "#,
+        issue_id,
+        last_email,
+        batch_size,
     )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(err) => Err(anyhow::anyhow!(err)),
-    })
-    .collect();
-
-    Ok(confirmed_subscribers)
+    .fetch_all(txn)
+    .await
 }
 
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, newsletter_layout, html_policy, topics, batch_size, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    newsletter_layout: web::Data<HtmlLayout>,
+    html_policy: web::Data<HtmlPolicy>,
+    topics: web::Data<NewsletterTopics>,
+    batch_size: web::Data<EnqueueBatchSize>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let subscribers = get_confirmed_subscriber(&pool).await?;
-    for sub in subscribers {
-        match sub {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter to {}", subscriber.email)
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    error.cause_chain = ?error,
-                    "skipping a confirmed subscriber. \
-                    Their stored contact details are invalid"
-                );
+    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let user_id = validate_credentials(credentials, &pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let content = body
+        .render(&newsletter_layout, &html_policy)
+        .map_err(PublishError::ValidationError)?;
+    IssueTemplates::parse(&content.html, &content.text).map_err(PublishError::ValidationError)?;
+    body.validate_topic(&topics.0)
+        .map_err(PublishError::ValidationError)?;
+    let lists = get_lists(&pool)
+        .await
+        .context("Failed to fetch the mailing lists")?;
+    let list_ids = body
+        .validate_lists(&lists)
+        .map_err(PublishError::ValidationError)?;
+
+    let idempotency_key = idempotency_key(request.headers())?;
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => match try_processing(&pool, idempotency_key, user_id).await? {
+            NextAction::StartProcessing(t) => t,
+            NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+        },
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire a postgres connection from the pool")?,
+    };
+    let issue_id = insert_newsletter_issue(&mut transaction, &body, &content, user_id)
+        .await
+        .context("Failed to store newsletter issue details")?;
+    set_issue_lists(&mut transaction, issue_id, &list_ids)
+        .await
+        .context("Failed to store the lists of the newsletter issue")?;
+    enqueue_issue_deliveries(&mut transaction, issue_id, batch_size.0).await?;
+
+    let response = HttpResponse::Accepted().finish();
+    match idempotency_key {
+        Some(idempotency_key) => {
+            let response = save_response(transaction, &idempotency_key, user_id, response).await?;
+            Ok(response)
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction")?;
+            Ok(response)
+        }
+    }
+}
+
+// Queues a delivery for every subscriber who wants the issue straight away,
+// adds it to the next digest of the others, and records how many recipients
+// the issue went out to directly. Subscribers are read `batch_size` at a time.
+pub async fn enqueue_issue_deliveries(
+    txn: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    batch_size: i64,
+) -> Result<(), anyhow::Error> {
+    let mut n_recipients = 0;
+    let mut last_email: Option<String> = None;
+    loop {
+        let batch = get_recipients_after(txn, issue_id, last_email.as_deref(), batch_size)
+            .await
+            .context("Failed to fetch confirmed subscribers")?;
+        let is_last_batch = (batch.len() as i64) < batch_size;
+        last_email = batch.last().map(|r| r.email.clone());
+
+        let mut recipients = Vec::new();
+        let mut digest_recipients = Vec::new();
+        for recipient in batch {
+            match SubscriberEmail::parse(recipient.email) {
+                Ok(email) if recipient.frequency == Frequency::WeeklyDigest.as_str() => {
+                    digest_recipients.push(email.as_ref().to_owned())
+                }
+                Ok(email) => recipients.push(email.as_ref().to_owned()),
+                Err(error) => {
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        "skipping a confirmed subscriber. \
+                        Their stored contact details are invalid"
+                    );
+                }
             }
         }
+        enqueue_delivery_tasks(txn, issue_id, &recipients)
+            .await
+            .context("Failed to enqueue delivery tasks")?;
+        add_to_digests(txn, issue_id, &digest_recipients)
+            .await
+            .context("Failed to add the issue to digests")?;
+        n_recipients += recipients.len() as i32;
+
+        if is_last_batch {
+            break;
+        }
     }
-    Ok(HttpResponse::Ok().finish())
+    set_recipient_count(txn, issue_id, n_recipients)
+        .await
+        .context("Failed to record the number of recipients")?;
+    Ok(())
+}
+
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<IdempotencyKey>, PublishError> {
+    let header_value = match headers.get("Idempotency-Key") {
+        Some(header_value) => header_value,
+        None => return Ok(None),
+    };
+    let idempotency_key = header_value
+        .to_str()
+        .context("The 'Idempotency-Key' header was not a valid UTF8 string")
+        .map_err(PublishError::InvalidIdempotencyKey)?
+        .to_string()
+        .try_into()
+        .map_err(PublishError::InvalidIdempotencyKey)?;
+    Ok(Some(idempotency_key))
+}
+
+#[tracing::instrument(name = "Store newsletter issue", skip_all)]
+async fn insert_newsletter_issue(
+    txn: &mut Transaction<'_, Postgres>,
+    body: &BodyData,
+    content: &RenderedContent,
+    author_id: Uuid,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            markdown_content,
+            tracking_enabled,
+            topic,
+            author_id,
+            status,
+            created_at,
+            published_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, 'published', now(), now())
+        "#,
+        newsletter_issue_id,
+        body.title,
+        content.text,
+        content.html,
+        content.markdown,
+        body.tracking,
+        body.topic,
+        author_id,
+    )
+    .execute(txn)
+    .await?;
+
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(name = "Record the number of recipients", skip(txn))]
+async fn set_recipient_count(
+    txn: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    n_recipients: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET n_recipients = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        n_recipients,
+    )
+    .execute(txn)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Enqueue delivery tasks", skip(txn, subscriber_emails))]
+async fn enqueue_delivery_tasks(
+    txn: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_emails: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT $1, email FROM UNNEST($2::text[]) as email
+        "#,
+        newsletter_issue_id,
+        subscriber_emails,
+    )
+    .execute(&mut *txn)
+    .await?;
+    let tracking_tokens: Vec<String> = subscriber_emails
+        .iter()
+        .map(|_| generate_tracking_token())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO deliveries (newsletter_issue_id, subscriber_email, status, tracking_token)
+        SELECT $1, email, 'pending', tracking_token
+        FROM UNNEST($2::text[], $3::text[]) as t(email, tracking_token)
+        "#,
+        newsletter_issue_id,
+        subscriber_emails,
+        &tracking_tokens,
+    )
+    .execute(txn)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Add an issue to digests", skip(txn, subscriber_emails))]
+async fn add_to_digests(
+    txn: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_emails: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO digest_entries (subscriber_email, newsletter_issue_id)
+        SELECT email, $1 FROM UNNEST($2::text[]) as email
+        "#,
+        newsletter_issue_id,
+        subscriber_emails,
+    )
+    .execute(txn)
+    .await?;
+    Ok(())
+}
+
+fn generate_tracking_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
 }
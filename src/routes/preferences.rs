@@ -0,0 +1,427 @@
+use crate::domain::{SubscriberName, Topic};
+use crate::routes::error_chain_fmt;
+use crate::startup::NewsletterTopics;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use pulldown_cmark::escape::escape_html;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+const MAX_PAUSE_WEEKS: i64 = 52;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    EveryIssue,
+    WeeklyDigest,
+}
+
+impl Frequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Frequency::EveryIssue => "every_issue",
+            Frequency::WeeklyDigest => "weekly_digest",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "every_issue" => Ok(Frequency::EveryIssue),
+            "weekly_digest" => Ok(Frequency::WeeklyDigest),
+            other => Err(format!(
+                "{} is not a valid frequency. Use either `every_issue` or `weekly_digest`",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesParameters {
+    token: String,
+}
+
+// The submitted form, as key/value pairs since every checked topic is sent as
+// its own `topics` field.
+#[derive(Debug)]
+pub struct PreferencesForm {
+    token: String,
+    name: String,
+    topics: Vec<String>,
+    frequency: String,
+    pause_weeks: Option<String>,
+}
+
+impl TryFrom<Vec<(String, String)>> for PreferencesForm {
+    type Error = String;
+
+    fn try_from(fields: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut token = None;
+        let mut name = None;
+        let mut topics = Vec::new();
+        let mut frequency = None;
+        let mut pause_weeks = None;
+        for (key, value) in fields {
+            match key.as_str() {
+                "token" => token = Some(value),
+                "name" => name = Some(value),
+                "topics" => topics.push(value),
+                "frequency" => frequency = Some(value),
+                "pause_weeks" if !value.trim().is_empty() => pause_weeks = Some(value),
+                _ => {}
+            }
+        }
+        Ok(Self {
+            token: token.ok_or("The `token` field is missing")?,
+            name: name.ok_or("The `name` field is missing")?,
+            topics,
+            frequency: frequency.ok_or("The `frequency` field is missing")?,
+            pause_weeks,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Preferences {
+    name: SubscriberName,
+    topics: Vec<String>,
+    frequency: Frequency,
+    // `Some(0)` resumes delivery, `None` leaves the pause as it is.
+    pause_weeks: Option<i64>,
+}
+
+impl PreferencesForm {
+    fn validate(self, available_topics: &[Topic]) -> Result<Preferences, String> {
+        let name = SubscriberName::parse(self.name)?;
+        if let Some(unknown) = self
+            .topics
+            .iter()
+            .find(|id| !available_topics.iter().any(|t| t.id == **id))
+        {
+            return Err(format!("{} is not a known topic", unknown));
+        }
+        let mut topics = self.topics;
+        topics.sort();
+        topics.dedup();
+        let frequency = Frequency::parse(&self.frequency)?;
+        let pause_weeks = match self.pause_weeks {
+            Some(weeks) => match weeks.trim().parse::<i64>() {
+                Ok(weeks) if (0..=MAX_PAUSE_WEEKS).contains(&weeks) => Some(weeks),
+                _ => {
+                    return Err(format!(
+                        "Delivery can be paused for 0 to {} weeks",
+                        MAX_PAUSE_WEEKS
+                    ))
+                }
+            },
+            None => None,
+        };
+        Ok(Preferences {
+            name,
+            topics,
+            frequency,
+            pause_weeks,
+        })
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("There is no subscriber associated with the provided token")]
+    UnknownToken,
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PreferencesError::UnknownToken => HttpResponse::new(StatusCode::UNAUTHORIZED),
+            PreferencesError::ValidationError(message) => {
+                HttpResponse::BadRequest().body(message.clone())
+            }
+            PreferencesError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+pub fn preferences_link(base_url: &str, token: &str) -> String {
+    format!("{}/preferences?token={}", base_url, token)
+}
+
+#[tracing::instrument(name = "Show subscriber preferences", skip(parameters, pool, topics))]
+pub async fn preferences_page(
+    parameters: web::Query<PreferencesParameters>,
+    pool: web::Data<PgPool>,
+    topics: web::Data<NewsletterTopics>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber = get_preferences(&pool, &parameters.token)
+        .await
+        .context("Failed to retrieve the subscriber preferences")?
+        .ok_or(PreferencesError::UnknownToken)?;
+    Ok(page(&parameters.token, &subscriber, &topics.0, None))
+}
+
+#[tracing::instrument(name = "Update subscriber preferences", skip(form, pool, topics))]
+pub async fn update_preferences(
+    form: web::Form<Vec<(String, String)>>,
+    pool: web::Data<PgPool>,
+    topics: web::Data<NewsletterTopics>,
+) -> Result<HttpResponse, PreferencesError> {
+    let form: PreferencesForm = form
+        .into_inner()
+        .try_into()
+        .map_err(PreferencesError::ValidationError)?;
+    let token = form.token.clone();
+    let preferences = form
+        .validate(&topics.0)
+        .map_err(PreferencesError::ValidationError)?;
+    let subscriber = store_preferences(&pool, &token, &preferences)
+        .await
+        .context("Failed to store the subscriber preferences")?
+        .ok_or(PreferencesError::UnknownToken)?;
+    Ok(page(
+        &token,
+        &subscriber,
+        &topics.0,
+        Some("Your preferences have been saved."),
+    ))
+}
+
+struct StoredPreferences {
+    name: String,
+    topics: Vec<String>,
+    frequency: String,
+    paused_until: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Get subscriber preferences", skip(pool, token))]
+async fn get_preferences(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<StoredPreferences>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredPreferences,
+        r#"
+        SELECT name, topics, frequency, paused_until
+        FROM subscriptions
+        WHERE
+            unsubscribe_token = $1 AND
+            status = 'confirmed'
+        "#,
+        token,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Store subscriber preferences", skip(pool, token, preferences))]
+async fn store_preferences(
+    pool: &PgPool,
+    token: &str,
+    preferences: &Preferences,
+) -> Result<Option<StoredPreferences>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredPreferences,
+        r#"
+        UPDATE subscriptions
+        SET
+            name = $2,
+            topics = $3,
+            frequency = $4,
+            paused_until = CASE
+                WHEN $5::bigint IS NULL THEN paused_until
+                WHEN $5 = 0 THEN NULL
+                ELSE now() + make_interval(weeks => $5::int)
+            END
+        WHERE
+            unsubscribe_token = $1 AND
+            status = 'confirmed'
+        RETURNING name, topics, frequency, paused_until
+        "#,
+        token,
+        preferences.name.as_ref(),
+        &preferences.topics,
+        preferences.frequency.as_str(),
+        preferences.pause_weeks,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    escape_html(&mut escaped, s).expect("Writing to a String cannot fail");
+    escaped
+}
+
+fn page(
+    token: &str,
+    subscriber: &StoredPreferences,
+    topics: &[Topic],
+    notice: Option<&str>,
+) -> HttpResponse {
+    let mut topic_fields = String::new();
+    for topic in topics {
+        let checked = if subscriber.topics.contains(&topic.id) {
+            " checked"
+        } else {
+            ""
+        };
+        writeln!(
+            topic_fields,
+            r#"<label><input type="checkbox" name="topics" value="{}"{}> {}</label><br>"#,
+            escape(&topic.id),
+            checked,
+            escape(&topic.name)
+        )
+        .unwrap();
+    }
+    let frequency_field = |value: &str, label: &str| {
+        let checked = if subscriber.frequency == value {
+            " checked"
+        } else {
+            ""
+        };
+        format!(
+            r#"<label><input type="radio" name="frequency" value="{}"{}> {}</label><br>"#,
+            value, checked, label
+        )
+    };
+    let pause_status = match subscriber.paused_until {
+        Some(paused_until) if paused_until > Utc::now() => format!(
+            "<p>Delivery is paused until {}. Enter 0 to resume it now.</p>",
+            paused_until.format("%B %-d, %Y")
+        ),
+        _ => String::new(),
+    };
+    let notice = notice
+        .map(|notice| format!("<p><strong>{}</strong></p>", notice))
+        .unwrap_or_default();
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your preferences</title>
+</head>
+<body>
+    {notice}
+    <form action="/preferences" method="post">
+        <input type="hidden" name="token" value="{token}">
+        <label>Name <input type="text" name="name" value="{name}"></label>
+        <fieldset>
+            <legend>Topics (leave empty to receive everything)</legend>
+            {topic_fields}
+        </fieldset>
+        <fieldset>
+            <legend>Frequency</legend>
+            {every_issue}
+            {weekly_digest}
+        </fieldset>
+        {pause_status}
+        <label>Pause delivery for <input type="number" name="pause_weeks" min="0" max="{max_pause_weeks}"> weeks</label>
+        <br>
+        <button type="submit">Save</button>
+    </form>
+</body>
+</html>"#,
+            notice = notice,
+            token = escape(token),
+            name = escape(&subscriber.name),
+            topic_fields = topic_fields,
+            every_issue = frequency_field(Frequency::EveryIssue.as_str(), "Every issue"),
+            weekly_digest = frequency_field(Frequency::WeeklyDigest.as_str(), "Weekly digest"),
+            pause_status = pause_status,
+            max_pause_weeks = MAX_PAUSE_WEEKS,
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::Topic;
+    use crate::routes::preferences::{Frequency, PreferencesForm};
+    use claim::assert_err;
+
+    fn topics() -> Vec<Topic> {
+        vec![Topic {
+            id: "rust".into(),
+            name: "Rust".into(),
+        }]
+    }
+
+    fn form(fields: &[(&str, &str)]) -> PreferencesForm {
+        fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn repeated_topic_fields_are_collected() {
+        let preferences = form(&[
+            ("token", "abc"),
+            ("name", "Ursula"),
+            ("topics", "rust"),
+            ("topics", "rust"),
+            ("frequency", "weekly_digest"),
+            ("pause_weeks", ""),
+        ])
+        .validate(&topics())
+        .unwrap();
+
+        assert_eq!(preferences.topics, ["rust"]);
+        assert_eq!(preferences.frequency, Frequency::WeeklyDigest);
+        assert_eq!(preferences.pause_weeks, None);
+    }
+
+    #[test]
+    fn unknown_topics_are_rejected() {
+        let form = form(&[
+            ("token", "abc"),
+            ("name", "Ursula"),
+            ("topics", "python"),
+            ("frequency", "every_issue"),
+        ]);
+
+        assert_err!(form.validate(&topics()));
+    }
+
+    #[test]
+    fn pauses_are_limited_to_a_year() {
+        for weeks in ["-1", "53", "two"] {
+            let form = form(&[
+                ("token", "abc"),
+                ("name", "Ursula"),
+                ("frequency", "every_issue"),
+                ("pause_weeks", weeks),
+            ]);
+
+            assert_err!(form.validate(&topics()));
+        }
+    }
+
+    #[test]
+    fn missing_fields_are_rejected() {
+        let fields = vec![("token".to_string(), "abc".to_string())];
+
+        assert_err!(PreferencesForm::try_from(fields));
+    }
+}
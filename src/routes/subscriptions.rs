@@ -1,16 +1,18 @@
 use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use crate::email_client::EmailClient;
-use crate::startup::ApplicationBaseUrl;
+use crate::email_outbox_worker::{enqueue_email, OutboxEmail};
+use crate::lists::{get_list_by_slug, MailingList, DEFAULT_LIST_SLUG};
+use crate::startup::{ApplicationBaseUrl, SubscriptionTokenTtl};
+use crate::suppression::SendKind;
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
 use chrono::Utc;
+use pulldown_cmark::escape::escape_html;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use sqlx::{PgPool, Postgres, Transaction};
 use std::error::Error;
-use std::fmt::{Display, Formatter};
-use std::ops::Sub;
+use std::fmt::Formatter;
 use uuid::Uuid;
 
 fn generate_subscription_token() -> String {
@@ -25,6 +27,8 @@ fn generate_subscription_token() -> String {
 pub struct FormData {
     email: String,
     name: String,
+    // The slug of the list to join, the default list if missing.
+    list: Option<String>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -40,7 +44,7 @@ impl TryFrom<FormData> for NewSubscriber {
 //noinspection RsTypeCheck
 #[tracing::instrument(
 name = "Adding a new subscriber",
-skip(form, conn_pool, email_client, base_url),
+skip(form, conn_pool, base_url, token_ttl),
 fields(
 subscriber_email = % form.email,
 subscriber_name = % form.name
@@ -49,65 +53,123 @@ subscriber_name = % form.name
 pub async fn subscribe(
     form: web::Form<FormData>,
     conn_pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let mut form = form.into_inner();
+    let list_slug = form
+        .list
+        .take()
+        .unwrap_or_else(|| DEFAULT_LIST_SLUG.to_string());
+    let new_subscriber: NewSubscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
     let mut transaction = conn_pool
         .begin()
         .await
         .context("Failed to acquire a postgres connection from the pool")?;
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let list = get_list_by_slug(&mut transaction, &list_slug)
         .await
-        .context("Failed to insert new subscriber into db")?;
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+        .context("Failed to look up the mailing list")?
+        .ok_or_else(|| {
+            SubscribeError::ValidationError(format!("{} is not a known list", list_slug))
+        })?;
+    let mut existing_subscriber =
+        get_subscriber_by_email(&mut transaction, &new_subscriber.email, list.list_id)
+            .await
+            .context("Failed to look up existing subscriber")?;
+    let mut inserted_subscriber_id = None;
+    if existing_subscriber.is_none() {
+        inserted_subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+            .await
+            .context("Failed to insert new subscriber into db")?;
+        if inserted_subscriber_id.is_none() {
+            // A concurrent request inserted the same address first. Its row is
+            // visible now, so carry on as for any existing subscriber.
+            existing_subscriber =
+                get_subscriber_by_email(&mut transaction, &new_subscriber.email, list.list_id)
+                    .await
+                    .context("Failed to look up existing subscriber")?;
+        }
+    }
+    let subscriber_id = match (inserted_subscriber_id, existing_subscriber) {
+        (Some(subscriber_id), _) => subscriber_id,
+        (None, None) => {
+            return Err(anyhow::anyhow!("The conflicting subscriber could not be found").into())
+        }
+        (None, Some(subscriber)) => match subscriber.status.as_str() {
+            "pending_confirmation" | "unsubscribed" => {
+                restart_subscription(&mut transaction, subscriber.id, &new_subscriber)
+                    .await
+                    .context("Failed to restart subscription")?;
+                expire_outstanding_tokens(&mut transaction, subscriber.id, list.list_id)
+                    .await
+                    .context("Failed to expire the previous confirmation tokens")?;
+                subscriber.id
+            }
+            // A confirmed subscriber joining another list confirms it too.
+            "confirmed" if subscriber.list_status.as_deref() != Some("confirmed") => subscriber.id,
+            // Answer exactly as we would for a new address, so the form cannot
+            // be used to find out who is on the list.
+            _ => return Ok(HttpResponse::Ok().finish()),
+        },
+    };
+    join_list(&mut transaction, list.list_id, subscriber_id)
         .await
-        .context("Failed to store confirmation token")?;
+        .context("Failed to add the subscriber to the list")?;
+    let subscription_token = generate_subscription_token();
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        list.list_id,
+        &subscription_token,
+        token_ttl.0,
+    )
+    .await
+    .context("Failed to store confirmation token")?;
+    // The email is only handed to the provider once this transaction has
+    // committed, so a provider outage can no longer fail the request.
+    enqueue_email(
+        &mut transaction,
+        &confirmation_email(new_subscriber, &list, &base_url.0, &subscription_token),
+    )
+    .await
+    .context("Failed to enqueue the confirmation email")?;
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction")?;
 
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send confirmation email")?;
-
     Ok(HttpResponse::Ok().finish())
 }
 
-#[tracing::instrument(
-    name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url, subscription_token)
-)]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
+pub fn confirmation_email(
     new_subscriber: NewSubscriber,
+    list: &MailingList,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> OutboxEmail {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
     let plain_body = format!(
-        "Welcome to our newsletter!\n Visit {} to confirm your subscription",
-        confirmation_link
+        "Welcome to our newsletter!\n Visit {} to confirm your subscription to {}",
+        confirmation_link, list.name
     );
+    let mut list_name = String::new();
+    escape_html(&mut list_name, &list.name).expect("Writing to a String cannot fail");
     let html_body = format!(
         "Welcome to our newsletter! <br />\
-            Click <a href=\"{}\">here</a> to confirm your subscription",
-        confirmation_link
+            Click <a href=\"{}\">here</a> to confirm your subscription to {}",
+        confirmation_link, list_name
     );
 
-    email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+    OutboxEmail {
+        recipient: new_subscriber.email,
+        subject: "Welcome!".into(),
+        html_content: html_body,
+        text_content: plain_body,
+        kind: SendKind::Transactional,
+    }
 }
 
 #[tracing::instrument(
@@ -117,13 +179,25 @@ pub async fn send_confirmation_email(
 pub async fn store_token(
     txn: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscription_token: &str,
+    ttl: chrono::Duration,
 ) -> Result<(), StoreTokenError> {
+    let created_at = Utc::now();
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)"#,
+        r#"INSERT INTO subscription_tokens (
+            subscription_token,
+            subscriber_id,
+            list_id,
+            created_at,
+            expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5)"#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        list_id,
+        created_at,
+        created_at + ttl
     )
     .execute(txn)
     .await
@@ -195,23 +269,142 @@ pub fn error_chain_fmt(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, txn)
 )]
+// Returns `None` if there already is a subscriber with the same address.
 pub async fn insert_subscriber(
     txn: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+    let unsubscribe_token = generate_subscription_token();
+    let inserted = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (
+            id,
+            email,
+            name,
+            subscribed_at,
+            status,
+            unsubscribe_token
+        )
+        VALUES ($1, $2, $3, $4, 'pending_confirmation', $5)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        unsubscribe_token
+    )
+    .fetch_optional(txn)
+    .await?;
+
+    Ok(inserted.map(|r| r.id))
+}
+
+struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+    list_status: Option<String>,
+}
+
+#[tracing::instrument(name = "Get subscriber by email", skip(txn, email))]
+async fn get_subscriber_by_email(
+    txn: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+    list_id: Uuid,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"
+        SELECT s.id, s.status, ls.status as "list_status?"
+        FROM subscriptions s
+        LEFT JOIN list_subscriptions ls ON
+            ls.subscriber_id = s.id AND
+            ls.list_id = $2
+        WHERE s.email = $1
+        FOR UPDATE OF s
+        "#,
+        email.as_ref(),
+        list_id,
+    )
+    .fetch_optional(txn)
+    .await
+}
+
+#[tracing::instrument(
+    name = "Restart a pending or cancelled subscription",
+    skip(txn, new_subscriber)
+)]
+async fn restart_subscription(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_subscriber: &NewSubscriber,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+            name = $2,
+            status = 'pending_confirmation',
+            unsubscribed_at = NULL
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        new_subscriber.name.as_ref(),
+    )
+    .execute(txn)
+    .await?;
+
+    Ok(())
+}
+
+// Only the link from the latest confirmation email can be used.
+#[tracing::instrument(name = "Expire outstanding confirmation tokens", skip(txn))]
+async fn expire_outstanding_tokens(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET expires_at = now()
+        WHERE
+            subscriber_id = $1 AND
+            list_id = $2 AND
+            used_at IS NULL AND
+            expires_at > now()
+        "#,
+        subscriber_id,
+        list_id,
+    )
+    .execute(txn)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Add a subscriber to a list", skip(txn))]
+async fn join_list(
+    txn: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, 'pending_confirmation', now())
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE
+        SET
+            status = 'pending_confirmation',
+            subscribed_at = now(),
+            unsubscribed_at = NULL
+        "#,
+        list_id,
+        subscriber_id,
     )
     .execute(txn)
     .await?;
 
-    Ok(subscriber_id)
+    Ok(())
 }
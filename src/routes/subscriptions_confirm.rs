@@ -1,5 +1,9 @@
-use actix_web::{web, HttpResponse};
-use sqlx::PgPool;
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -7,54 +11,159 @@ pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-
-    match id {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("There is no subscriber associated with the provided token")]
+    UnknownToken,
+    #[error("The subscription token has expired")]
+    ExpiredToken,
+    #[error("The subscription token has already been used")]
+    TokenAlreadyUsed,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ConfirmError::ExpiredToken => StatusCode::GONE,
+            ConfirmError::TokenAlreadyUsed => StatusCode::CONFLICT,
+            ConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#" UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+struct StoredToken {
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    expires_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ConfirmError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let token = get_token(&mut transaction, &parameters.subscription_token)
+        .await
+        .context("Failed to retrieve the subscription token")?
+        .ok_or(ConfirmError::UnknownToken)?;
+
+    if token.used_at.is_some() {
+        return Err(ConfirmError::TokenAlreadyUsed);
+    }
+    if token.expires_at <= Utc::now() {
+        return Err(ConfirmError::ExpiredToken);
+    }
+
+    mark_token_as_used(&mut transaction, &parameters.subscription_token)
+        .await
+        .context("Failed to mark the subscription token as used")?;
+    let subscriber_confirmed = confirm_subscriber(&mut transaction, token.subscriber_id)
+        .await
+        .context("Failed to mark subscriber as confirmed")?;
+    let list_confirmed =
+        confirm_list_subscription(&mut transaction, token.list_id, token.subscriber_id)
+            .await
+            .context("Failed to confirm the subscription to the list")?;
+    // Nothing was waiting for this token, e.g. the subscriber unsubscribed
+    // before confirming.
+    if !subscriber_confirmed && !list_confirmed {
+        return Err(ConfirmError::ExpiredToken);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// Returns whether the subscriber was pending confirmation.
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, txn))]
+pub async fn confirm_subscriber(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'confirmed', confirmed_at = now()
+        WHERE id = $1 AND status = 'pending_confirmation'
+        "#,
         subscriber_id,
     )
-    .execute(pool)
+    .execute(txn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// Returns whether the subscription to the list was pending confirmation.
+#[tracing::instrument(name = "Confirm a list subscription", skip(txn))]
+async fn confirm_list_subscription(
+    txn: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = 'confirmed', confirmed_at = now()
+        WHERE
+            list_id = $1 AND
+            subscriber_id = $2 AND
+            status = 'pending_confirmation'
+        "#,
+        list_id,
+        subscriber_id,
+    )
+    .execute(txn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[tracing::instrument(name = "Get subscription token", skip(subscription_token, txn))]
+async fn get_token(
+    txn: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredToken,
+        "SELECT subscriber_id, list_id, expires_at, used_at FROM subscription_tokens \
+        WHERE subscription_token = $1 \
+        FOR UPDATE",
+        subscription_token,
+    )
+    .fetch_optional(txn)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(())
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+#[tracing::instrument(
+    name = "Mark subscription token as used",
+    skip(subscription_token, txn)
+)]
+async fn mark_token_as_used(
+    txn: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens \
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE subscription_tokens SET used_at = now() \
         WHERE subscription_token = $1",
         subscription_token,
     )
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(result.map(|r| r.subscriber_id))
+    .execute(txn)
+    .await?;
+    Ok(())
 }
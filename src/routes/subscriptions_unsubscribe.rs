@@ -0,0 +1,245 @@
+use crate::lists::get_list_by_slug;
+use crate::routes::error_chain_fmt;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use pulldown_cmark::escape::escape_html;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    unsubscribe_token: String,
+    // Leave a single list instead of all of them.
+    list: Option<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("There is no subscriber associated with the provided token")]
+    UnknownToken,
+    #[error("{0} is not a known list")]
+    UnknownList(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::UnknownToken => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::UnknownList(_) => StatusCode::BAD_REQUEST,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+pub fn unsubscribe_link(base_url: &str, unsubscribe_token: &str) -> String {
+    format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+        base_url, unsubscribe_token
+    )
+}
+
+// Opening the link only asks for a confirmation: mail scanners and link
+// previews follow links in emails, and they must not unsubscribe anyone. The
+// form posts back to the same URL, which is also the one-click target.
+#[tracing::instrument(name = "Show the unsubscribe form", skip(parameters, pool))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let list = match &parameters.list {
+        Some(slug) => Some(
+            get_list_by_slug(&mut transaction, slug)
+                .await
+                .context("Failed to look up the mailing list")?
+                .ok_or_else(|| UnsubscribeError::UnknownList(slug.clone()))?,
+        ),
+        None => None,
+    };
+    get_subscriber_id_from_token(&mut transaction, &parameters.unsubscribe_token)
+        .await
+        .context("Failed to retrieve the subscriber id associated with the token")?
+        .ok_or(UnsubscribeError::UnknownToken)?;
+
+    let mut action = format!(
+        "/subscriptions/unsubscribe?unsubscribe_token={}",
+        parameters.unsubscribe_token
+    );
+    let mut question = String::from("Do you want to stop receiving all our emails?");
+    if let Some(list) = &list {
+        action.push_str(&format!("&list={}", list.slug));
+        question = format!("Do you want to stop receiving {}?", list.name);
+    }
+    let mut escaped_action = String::new();
+    escape_html(&mut escaped_action, &action).context("Failed to escape the form action")?;
+    let mut escaped_question = String::new();
+    escape_html(&mut escaped_question, &question).context("Failed to escape the question")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>{}</p>
+    <form action="{}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            escaped_question, escaped_action
+        )))
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, pool))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let list_id = match &parameters.list {
+        Some(slug) => {
+            let list = get_list_by_slug(&mut transaction, slug)
+                .await
+                .context("Failed to look up the mailing list")?
+                .ok_or_else(|| UnsubscribeError::UnknownList(slug.clone()))?;
+            Some(list.list_id)
+        }
+        None => None,
+    };
+    let subscriber_id =
+        get_subscriber_id_from_token(&mut transaction, &parameters.unsubscribe_token)
+            .await
+            .context("Failed to retrieve the subscriber id associated with the token")?
+            .ok_or(UnsubscribeError::UnknownToken)?;
+    if list_id.is_none() {
+        mark_subscriber_as_unsubscribed(&mut transaction, subscriber_id)
+            .await
+            .context("Failed to mark subscriber as unsubscribed")?;
+    }
+    leave_lists(&mut transaction, subscriber_id, list_id)
+        .await
+        .context("Failed to unsubscribe from the lists")?;
+    drop_unreachable_digest_entries(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to remove issues from the subscriber's digest")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Get subscriber id from token", skip(unsubscribe_token, txn))]
+async fn get_subscriber_id_from_token(
+    txn: &mut Transaction<'_, Postgres>,
+    unsubscribe_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE unsubscribe_token = $1",
+        unsubscribe_token,
+    )
+    .fetch_optional(txn)
+    .await?;
+    Ok(result.map(|r| r.id))
+}
+
+// Every list of the subscriber when `list_id` is `None`.
+#[tracing::instrument(name = "Leave mailing lists", skip(txn))]
+async fn leave_lists(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET
+            status = 'unsubscribed',
+            unsubscribed_at = COALESCE(unsubscribed_at, now())
+        WHERE
+            subscriber_id = $1 AND
+            ($2::uuid IS NULL OR list_id = $2)
+        "#,
+        subscriber_id,
+        list_id,
+    )
+    .execute(txn)
+    .await?;
+    Ok(())
+}
+
+// Issues waiting in the digest that were published to none of the lists the
+// subscriber is still on.
+#[tracing::instrument(name = "Drop unreachable digest entries", skip(txn))]
+async fn drop_unreachable_digest_entries(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM digest_entries e
+        USING subscriptions s
+        WHERE
+            s.id = $1 AND
+            e.subscriber_email = s.email AND
+            NOT EXISTS (
+                SELECT 1
+                FROM newsletter_issue_lists il
+                JOIN list_subscriptions ls ON ls.list_id = il.list_id
+                WHERE
+                    il.newsletter_issue_id = e.newsletter_issue_id AND
+                    ls.subscriber_id = s.id AND
+                    ls.status = 'confirmed'
+            )
+        "#,
+        subscriber_id,
+    )
+    .execute(txn)
+    .await?;
+    Ok(())
+}
+
+// Bounced and complained addresses keep their status, so they cannot be
+// subscribed again through the form.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(txn))]
+async fn mark_subscriber_as_unsubscribed(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+            status = 'unsubscribed',
+            unsubscribed_at = COALESCE(unsubscribed_at, now())
+        WHERE
+            id = $1 AND
+            status NOT IN ('bounced', 'complained')
+        "#,
+        subscriber_id,
+    )
+    .execute(txn)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,298 @@
+use crate::routes::error_chain_fmt;
+use crate::startup::TrackingEnabled;
+use actix_web::http::header::{CacheControl, CacheDirective, LOCATION};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Filled in per recipient with their delivery's tracking token.
+pub const TRACKING_TOKEN_PLACEHOLDER: &str = "{{tracking_token}}";
+
+// A transparent 1x1 GIF.
+const PIXEL: [u8; 43] = [
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(serde::Deserialize)]
+pub struct ClickParameters {
+    link: i32,
+}
+
+#[derive(thiserror::Error)]
+pub enum TrackingError {
+    #[error("The tracked link does not exist")]
+    UnknownLink,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TrackingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TrackingError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TrackingError::UnknownLink => StatusCode::NOT_FOUND,
+            TrackingError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// Points every web link of an HTML body at the click endpoint. Returns the
+// rewritten body along with the original links, by index.
+pub fn track_links(html: &str, base_url: &str) -> (String, Vec<String>) {
+    let mut tracked = String::with_capacity(html.len());
+    let mut links: Vec<String> = Vec::new();
+    let mut rest = html;
+    while let Some(start) = find_anchor(rest) {
+        let end = rest[start..]
+            .find('>')
+            .map(|i| start + i)
+            .unwrap_or(rest.len());
+        let tag = &rest[start..end];
+        tracked.push_str(&rest[..start]);
+        match href_value(tag) {
+            Some((value_start, value_end)) => {
+                let url = unescape_attribute(&tag[value_start..value_end]);
+                // Links filled in per recipient cannot be stored once for
+                // the whole issue.
+                let is_web_link = url.starts_with("https://") || url.starts_with("http://");
+                if is_web_link && !url.contains("{{") {
+                    let index = match links.iter().position(|link| *link == url) {
+                        Some(index) => index,
+                        None => {
+                            links.push(url);
+                            links.len() - 1
+                        }
+                    };
+                    tracked.push_str(&tag[..value_start]);
+                    tracked.push_str(&click_link(base_url, index));
+                    tracked.push_str(&tag[value_end..]);
+                } else {
+                    tracked.push_str(tag);
+                }
+            }
+            None => tracked.push_str(tag),
+        }
+        rest = &rest[end..];
+    }
+    tracked.push_str(rest);
+    (tracked, links)
+}
+
+pub fn open_pixel(base_url: &str) -> String {
+    format!(
+        r#"<img src="{}/t/open/{}" width="1" height="1" alt="">"#,
+        base_url, TRACKING_TOKEN_PLACEHOLDER
+    )
+}
+
+fn click_link(base_url: &str, index: usize) -> String {
+    format!(
+        "{}/t/click/{}?link={}",
+        base_url, TRACKING_TOKEN_PLACEHOLDER, index
+    )
+}
+
+fn find_anchor(html: &str) -> Option<usize> {
+    html.match_indices("<a")
+        .map(|(i, _)| i)
+        .find(|i| html[i + 2..].starts_with(|c: char| c.is_ascii_whitespace()))
+}
+
+// The byte range of the value of the `href` attribute within an opening tag.
+fn href_value(tag: &str) -> Option<(usize, usize)> {
+    let attribute = tag
+        .match_indices("href=")
+        .map(|(i, _)| i)
+        .find(|i| tag[..*i].ends_with(|c: char| c.is_ascii_whitespace()))?;
+    let value = attribute + "href=".len();
+    let quote = tag[value..]
+        .chars()
+        .next()
+        .filter(|c| *c == '"' || *c == '\'')?;
+    let end = tag[value + 1..].find(quote)?;
+    Some((value + 1, value + 1 + end))
+}
+
+fn unescape_attribute(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+struct TrackedDelivery {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+#[tracing::instrument(name = "Track an email open", skip(token, pool, tracking_enabled))]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracking_enabled: web::Data<TrackingEnabled>,
+) -> Result<HttpResponse, TrackingError> {
+    if tracking_enabled.0 {
+        if let Some(delivery) = get_delivery(&pool, &token)
+            .await
+            .context("Failed to retrieve the tracked delivery")?
+        {
+            record_event(&pool, &delivery, "open", None)
+                .await
+                .context("Failed to record an open")?;
+        }
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(PIXEL.to_vec()))
+}
+
+#[tracing::instrument(
+    name = "Track a link click",
+    skip(token, parameters, pool, tracking_enabled)
+)]
+pub async fn track_click(
+    token: web::Path<String>,
+    parameters: web::Query<ClickParameters>,
+    pool: web::Data<PgPool>,
+    tracking_enabled: web::Data<TrackingEnabled>,
+) -> Result<HttpResponse, TrackingError> {
+    let delivery = get_delivery(&pool, &token)
+        .await
+        .context("Failed to retrieve the tracked delivery")?
+        .ok_or(TrackingError::UnknownLink)?;
+    let url = get_link(&pool, delivery.newsletter_issue_id, parameters.link)
+        .await
+        .context("Failed to retrieve the tracked link")?
+        .ok_or(TrackingError::UnknownLink)?;
+    if tracking_enabled.0 {
+        record_event(&pool, &delivery, "click", Some(parameters.link))
+            .await
+            .context("Failed to record a click")?;
+    }
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, url))
+        .finish())
+}
+
+#[tracing::instrument(name = "Get the delivery of a tracking token", skip(pool, token))]
+async fn get_delivery(pool: &PgPool, token: &str) -> Result<Option<TrackedDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        TrackedDelivery,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email
+        FROM deliveries
+        WHERE tracking_token = $1
+        "#,
+        token,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get a tracked link", skip(pool))]
+async fn get_link(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    link_index: i32,
+) -> Result<Option<String>, sqlx::Error> {
+    let link = sqlx::query!(
+        r#"
+        SELECT url
+        FROM issue_links
+        WHERE
+            newsletter_issue_id = $1 AND
+            link_index = $2
+        "#,
+        newsletter_issue_id,
+        link_index,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(link.map(|l| l.url))
+}
+
+// Repeated opens of a delivery are ignored, only its first one is stored.
+#[tracing::instrument(name = "Record a tracking event", skip(pool, delivery))]
+async fn record_event(
+    pool: &PgPool,
+    delivery: &TrackedDelivery,
+    kind: &str,
+    link_index: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO tracking_events (
+            event_id,
+            newsletter_issue_id,
+            subscriber_email,
+            kind,
+            link_index
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        delivery.newsletter_issue_id,
+        delivery.subscriber_email,
+        kind,
+        link_index,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::tracking::track_links;
+
+    #[test]
+    fn web_links_are_pointed_at_the_click_endpoint() {
+        let (html, links) = track_links(
+            r#"<p><a href="https://example.com/a?x=1&amp;y=2">A</a> <a class="b" href='http://example.com/b'>B</a></p>"#,
+            "https://news.example.com",
+        );
+
+        assert_eq!(
+            html,
+            r#"<p><a href="https://news.example.com/t/click/{{tracking_token}}?link=0">A</a> <a class="b" href='https://news.example.com/t/click/{{tracking_token}}?link=1'>B</a></p>"#
+        );
+        assert_eq!(
+            links,
+            ["https://example.com/a?x=1&y=2", "http://example.com/b"]
+        );
+    }
+
+    #[test]
+    fn repeated_links_share_an_index() {
+        let (html, links) = track_links(
+            r#"<a href="https://example.com">1</a><a href="https://example.com">2</a>"#,
+            "",
+        );
+
+        assert_eq!(links.len(), 1);
+        assert_eq!(html.matches("?link=0").count(), 2);
+    }
+
+    #[test]
+    fn other_links_are_left_untouched() {
+        let html = r#"<a href="mailto:me@example.com">Mail</a><a href="{{unsubscribe_url|html}}">Bye</a><a href="https://example.com/?name={{ name }}">Me</a><abbr>X</abbr>"#;
+
+        let (tracked, links) = track_links(html, "https://news.example.com");
+
+        assert_eq!(tracked, html);
+        assert!(links.is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+use html5ever::tendril::StrTendril;
+use html5ever::tokenizer::{
+    BufferQueue, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts,
+};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+// Elements whose end tag HTML allows to be left out.
+const OPTIONAL_END_TAG_ELEMENTS: [&str; 14] = [
+    "html", "head", "body", "p", "li", "dt", "dd", "option", "thead", "tbody", "tfoot", "tr", "td",
+    "th",
+];
+
+// Style declarations containing any of these could load a remote resource. A
+// backslash could hide one of them behind a CSS escape.
+const UNSAFE_STYLE_PATTERNS: [&str; 5] = ["url(", "image-set(", "@import", "expression(", "\\"];
+
+// What newsletter HTML written by admins is allowed to contain.
+#[derive(Debug, Clone)]
+pub struct HtmlPolicy {
+    max_bytes: usize,
+    // Images from other hosts are dropped, so they cannot be used as
+    // tracking pixels.
+    allowed_image_hosts: Arc<HashSet<String>>,
+}
+
+impl HtmlPolicy {
+    pub fn new(max_bytes: usize, allowed_image_hosts: Vec<String>) -> Self {
+        Self {
+            max_bytes,
+            allowed_image_hosts: Arc::new(allowed_image_hosts.into_iter().collect()),
+        }
+    }
+
+    // Returns every problem found, or the HTML stripped of anything outside
+    // the allowlist.
+    pub fn clean(&self, html: &str) -> Result<String, Vec<String>> {
+        self.check_size(html)?;
+        let problems = structural_problems(html);
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+        Ok(self.sanitizer().clean(html).to_string())
+    }
+
+    pub fn check_size(&self, html: &str) -> Result<(), Vec<String>> {
+        if html.len() > self.max_bytes {
+            Err(vec![format!(
+                "The HTML body is {} bytes long, the limit is {} bytes",
+                html.len(),
+                self.max_bytes
+            )])
+        } else {
+            Ok(())
+        }
+    }
+
+    fn sanitizer(&self) -> ammonia::Builder<'static> {
+        let allowed_image_hosts = self.allowed_image_hosts.clone();
+        let mut builder = ammonia::Builder::default();
+        // Email clients ignore stylesheets, so newsletters are laid out with
+        // inline styles and table attributes.
+        builder
+            .add_generic_attributes(&["style", "width", "align", "bgcolor", "valign"])
+            .add_tag_attributes("table", &["border", "cellpadding", "cellspacing"]);
+        builder.attribute_filter(move |element, attribute, value| {
+            if element == "img" && attribute == "src" {
+                let host = reqwest::Url::parse(value)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_owned));
+                match host {
+                    Some(host) if allowed_image_hosts.contains(&host) => Some(value.into()),
+                    _ => None,
+                }
+            } else if attribute == "style" {
+                clean_style(value).map(Into::into)
+            } else {
+                Some(value.into())
+            }
+        });
+        builder
+    }
+}
+
+// Drops the declarations that could load an image from anywhere, which would
+// get around the image host allowlist.
+fn clean_style(style: &str) -> Option<String> {
+    let declarations: Vec<_> = style
+        .split(';')
+        .map(str::trim)
+        .filter(|declaration| {
+            let declaration = declaration.to_lowercase();
+            !declaration.is_empty()
+                && !UNSAFE_STYLE_PATTERNS
+                    .iter()
+                    .any(|pattern| declaration.contains(pattern))
+        })
+        .collect();
+    if declarations.is_empty() {
+        None
+    } else {
+        Some(declarations.join("; "))
+    }
+}
+
+#[derive(Default)]
+struct StructureChecker {
+    open_elements: Vec<String>,
+    problems: Vec<String>,
+}
+
+impl TokenSink for StructureChecker {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) => {
+                let name = tag.name.to_string();
+                match tag.kind {
+                    TagKind::StartTag => {
+                        if !tag.self_closing && !VOID_ELEMENTS.contains(&name.as_str()) {
+                            self.open_elements.push(name);
+                        }
+                    }
+                    TagKind::EndTag => match self.open_elements.iter().rposition(|e| *e == name) {
+                        Some(i) => {
+                            for unclosed in self.open_elements.drain(i..).skip(1) {
+                                if !OPTIONAL_END_TAG_ELEMENTS.contains(&unclosed.as_str()) {
+                                    self.problems.push(format!(
+                                        "Line {}: <{}> is closed by </{}>",
+                                        line_number, unclosed, name
+                                    ));
+                                }
+                            }
+                        }
+                        None => self.problems.push(format!(
+                            "Line {}: </{}> does not match any open element",
+                            line_number, name
+                        )),
+                    },
+                }
+            }
+            Token::ParseError(error) => {
+                self.problems
+                    .push(format!("Line {}: {}", line_number, error));
+            }
+            _ => {}
+        }
+        TokenSinkResult::Continue
+    }
+}
+
+fn structural_problems(html: &str) -> Vec<String> {
+    let mut tokenizer = Tokenizer::new(StructureChecker::default(), TokenizerOpts::default());
+    let mut input = BufferQueue::new();
+    input.push_back(StrTendril::from_slice(html));
+    let _ = tokenizer.feed(&mut input);
+    tokenizer.end();
+
+    let mut checker = tokenizer.sink;
+    for unclosed in checker.open_elements.drain(..) {
+        if !OPTIONAL_END_TAG_ELEMENTS.contains(&unclosed.as_str()) {
+            checker
+                .problems
+                .push(format!("<{}> is never closed", unclosed));
+        }
+    }
+    checker.problems
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sanitization::HtmlPolicy;
+
+    fn policy() -> HtmlPolicy {
+        HtmlPolicy::new(1024, vec!["cdn.example.com".into()])
+    }
+
+    #[test]
+    fn scripts_and_event_handlers_are_removed() {
+        let html = policy()
+            .clean(r#"<p onclick="steal()">Hi</p><script>alert(1)</script>"#)
+            .unwrap();
+
+        assert_eq!(html, "<p>Hi</p>");
+    }
+
+    #[test]
+    fn allowed_markup_and_template_variables_are_kept() {
+        let html = policy()
+            .clean(
+                r#"<p>Hi <strong>{{ name }}</strong>, <a href="{{ unsubscribe_url }}">bye</a></p>"#,
+            )
+            .unwrap();
+
+        assert!(html.contains("<strong>{{ name }}</strong>"));
+        assert!(html.contains(r#"href="{{ unsubscribe_url }}""#));
+    }
+
+    #[test]
+    fn inline_styles_and_table_layout_attributes_are_kept() {
+        let html = policy()
+            .clean(
+                r##"<table width="600" align="center" bgcolor="#ffffff" border="0" cellpadding="8" cellspacing="0"><tr><td valign="top" style="color: #333333">Hi</td></tr></table>"##,
+            )
+            .unwrap();
+
+        for attribute in [
+            r#"width="600""#,
+            r#"align="center""#,
+            r##"bgcolor="#ffffff""##,
+            r#"border="0""#,
+            r#"cellpadding="8""#,
+            r#"cellspacing="0""#,
+            r#"valign="top""#,
+            r##"style="color: #333333""##,
+        ] {
+            assert!(html.contains(attribute), "{} was removed", attribute);
+        }
+    }
+
+    #[test]
+    fn images_from_unknown_hosts_lose_their_source() {
+        let html = policy()
+            .clean(
+                r#"<img src="https://tracker.example.net/p.gif"><img src="https://cdn.example.com/logo.png">"#,
+            )
+            .unwrap();
+
+        assert!(!html.contains("tracker.example.net"));
+        assert!(html.contains("https://cdn.example.com/logo.png"));
+    }
+
+    #[test]
+    fn inline_styles_cannot_load_remote_images() {
+        let html = policy()
+            .clean(
+                r#"<table><tr><td style="color: red; background:URL(https://tracker.example.net/p.gif)">Hi</td><td style="background-image: u\72l(https://tracker.example.net/p.gif)">Bye</td></tr></table>"#,
+            )
+            .unwrap();
+
+        assert!(!html.contains("tracker.example.net"));
+        assert!(html.contains(r#"<td style="color: red">Hi</td>"#));
+        assert!(html.contains("<td>Bye</td>"));
+    }
+
+    #[test]
+    fn unclosed_and_stray_tags_are_reported() {
+        let problems = policy()
+            .clean("<div><em>Hi</div></span><table>")
+            .unwrap_err();
+
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].contains("<em>"));
+        assert!(problems[1].contains("</span>"));
+        assert!(problems[2].contains("<table>"));
+    }
+
+    #[test]
+    fn optional_end_tags_are_not_reported() {
+        assert!(policy().clean("<ul><li>One<li>Two</ul><p>Text").is_ok());
+    }
+
+    #[test]
+    fn oversized_bodies_are_rejected() {
+        let problems = policy().clean(&"a".repeat(1025)).unwrap_err();
+
+        assert!(problems[0].contains("1024"));
+    }
+}
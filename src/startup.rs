@@ -1,9 +1,19 @@
 use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
-use crate::routes::{confirm, health_check, publish_newsletter, subscribe};
+use crate::domain::{SubscriberEmail, Topic};
+use crate::markdown::HtmlLayout;
+use crate::routes::{
+    add_suppression, cancel_issue, confirm, create_draft, create_list, delete_draft,
+    get_delivery_report, get_issue, get_issue_stats, get_suppression_stats, health_check,
+    list_issues, list_lists, list_suppressions, preferences_page, preview_issue,
+    publish_newsletter, receive_email_events, remove_suppression, retry_failed_deliveries,
+    schedule_issue, subscribe, track_click, track_open, unsubscribe, unsubscribe_form,
+    update_draft, update_preferences, WebhookSigningKey,
+};
+use crate::sanitization::HtmlPolicy;
 use actix_web::dev::Server;
 use actix_web::web::Data;
 use actix_web::{web, App, HttpServer};
+use secrecy::Secret;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
@@ -17,30 +27,39 @@ pub struct Application {
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
+        let webhook_signing_key = configuration.email_client.webhook_signing_key.clone();
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address");
-
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
         );
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
+        let subscription_token_ttl = configuration.application.subscription_token_ttl();
+        let preview_recipients = configuration
+            .application
+            .preview_recipients()
+            .expect("Invalid preview recipient email address");
+        let newsletter_layout = configuration
+            .application
+            .newsletter_layout()
+            .expect("Invalid newsletter layout");
+        let html_policy = configuration.application.html_policy();
+        let tracking_enabled = configuration.application.tracking_enabled;
+        let newsletter_topics = configuration.application.newsletter_topics.clone();
+        let enqueue_batch_size = configuration.application.enqueue_batch_size;
         let server = run(
             listener,
             connection_pool,
-            email_client,
             configuration.application.base_url,
+            subscription_token_ttl,
+            preview_recipients,
+            newsletter_layout,
+            html_policy,
+            tracking_enabled,
+            webhook_signing_key,
+            newsletter_topics,
+            enqueue_batch_size,
         )?;
         Ok(Self { port, server })
     }
@@ -56,25 +75,111 @@ impl Application {
 
 pub struct ApplicationBaseUrl(pub String);
 
+pub struct SubscriptionTokenTtl(pub chrono::Duration);
+
+pub struct PreviewRecipients(pub Vec<SubscriberEmail>);
+
+pub struct TrackingEnabled(pub bool);
+
+pub struct NewsletterTopics(pub Vec<Topic>);
+
+pub struct EnqueueBatchSize(pub i64);
+
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     conn_pool: PgPool,
-    email_client: EmailClient,
     base_url: String,
+    subscription_token_ttl: chrono::Duration,
+    preview_recipients: Vec<SubscriberEmail>,
+    newsletter_layout: HtmlLayout,
+    html_policy: HtmlPolicy,
+    tracking_enabled: bool,
+    webhook_signing_key: Secret<String>,
+    newsletter_topics: Vec<Topic>,
+    enqueue_batch_size: i64,
 ) -> Result<Server, std::io::Error> {
     let conn_pool = web::Data::new(conn_pool);
-    let email_client = web::Data::new(email_client);
     let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let subscription_token_ttl = Data::new(SubscriptionTokenTtl(subscription_token_ttl));
+    let preview_recipients = Data::new(PreviewRecipients(preview_recipients));
+    let newsletter_layout = Data::new(newsletter_layout);
+    let html_policy = Data::new(html_policy);
+    let tracking_enabled = Data::new(TrackingEnabled(tracking_enabled));
+    let webhook_signing_key = Data::new(WebhookSigningKey(webhook_signing_key));
+    let newsletter_topics = Data::new(NewsletterTopics(newsletter_topics));
+    let enqueue_batch_size = Data::new(EnqueueBatchSize(enqueue_batch_size));
     let server = HttpServer::new(move || {
         App::new()
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/preferences", web::get().to(preferences_page))
+            .route("/preferences", web::post().to(update_preferences))
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/t/open/{token}", web::get().to(track_open))
+            .route("/t/click/{token}", web::get().to(track_click))
+            .route(
+                "/webhooks/email-events",
+                web::post().to(receive_email_events),
+            )
+            .route("/admin/issues", web::get().to(list_issues))
+            .route("/admin/issues", web::post().to(create_draft))
+            .route("/admin/issues/{issue_id}", web::get().to(get_issue))
+            .route("/admin/issues/{issue_id}", web::put().to(update_draft))
+            .route("/admin/issues/{issue_id}", web::delete().to(delete_draft))
+            .route(
+                "/admin/issues/{issue_id}/preview",
+                web::post().to(preview_issue),
+            )
+            .route(
+                "/admin/issues/{issue_id}/schedule",
+                web::post().to(schedule_issue),
+            )
+            .route(
+                "/admin/issues/{issue_id}/cancel",
+                web::post().to(cancel_issue),
+            )
+            .route(
+                "/admin/issues/{issue_id}/deliveries",
+                web::get().to(get_delivery_report),
+            )
+            .route(
+                "/admin/issues/{issue_id}/deliveries/retry",
+                web::post().to(retry_failed_deliveries),
+            )
+            .route(
+                "/admin/issues/{issue_id}/stats",
+                web::get().to(get_issue_stats),
+            )
+            .route("/admin/lists", web::get().to(list_lists))
+            .route("/admin/lists", web::post().to(create_list))
+            .route("/admin/suppressions", web::get().to(list_suppressions))
+            .route("/admin/suppressions", web::post().to(add_suppression))
+            .route(
+                "/admin/suppressions/stats",
+                web::get().to(get_suppression_stats),
+            )
+            .route(
+                "/admin/suppressions/{suppression_id}",
+                web::delete().to(remove_suppression),
+            )
             .app_data(conn_pool.clone())
-            .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(subscription_token_ttl.clone())
+            .app_data(preview_recipients.clone())
+            .app_data(newsletter_layout.clone())
+            .app_data(html_policy.clone())
+            .app_data(tracking_enabled.clone())
+            .app_data(webhook_signing_key.clone())
+            .app_data(newsletter_topics.clone())
+            .app_data(enqueue_batch_size.clone())
     })
     .listen(listener)?
     .run();
@@ -83,7 +188,15 @@ pub fn run(
 }
 
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
+    get_connection_pool_with_size(configuration, 10)
+}
+
+pub fn get_connection_pool_with_size(
+    configuration: &DatabaseSettings,
+    max_connections: u32,
+) -> PgPool {
     PgPoolOptions::new()
+        .max_connections(max_connections)
         .acquire_timeout(std::time::Duration::from_secs(2))
         .connect_lazy_with(configuration.with_db())
 }
@@ -0,0 +1,164 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, Transaction};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+// What a suppression applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SuppressionTarget {
+    Address(String),
+    Domain(String),
+}
+
+impl SuppressionTarget {
+    fn address(&self) -> Option<&str> {
+        match self {
+            SuppressionTarget::Address(address) => Some(address),
+            SuppressionTarget::Domain(_) => None,
+        }
+    }
+
+    fn domain(&self) -> Option<&str> {
+        match self {
+            SuppressionTarget::Address(_) => None,
+            SuppressionTarget::Domain(domain) => Some(domain),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SendKind {
+    // Confirmation and preview emails, which go through the outbox.
+    Transactional,
+    // Weekly digests, which go through the outbox as well.
+    Digest,
+    Newsletter(Uuid),
+}
+
+impl SendKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SendKind::Transactional => "transactional",
+            SendKind::Digest => "digest",
+            SendKind::Newsletter(_) => "newsletter",
+        }
+    }
+
+    // The kind stored alongside an outbox email.
+    pub fn from_outbox(kind: &str) -> Self {
+        match kind {
+            "digest" => SendKind::Digest,
+            _ => SendKind::Transactional,
+        }
+    }
+
+    fn newsletter_issue_id(&self) -> Option<Uuid> {
+        match self {
+            SendKind::Transactional | SendKind::Digest => None,
+            SendKind::Newsletter(issue_id) => Some(*issue_id),
+        }
+    }
+}
+
+// Adds a suppression, replacing an expired one for the same target. Returns
+// `None` if the target is already suppressed. Issues waiting in the digests of
+// the suppressed addresses are dropped.
+#[tracing::instrument(name = "Add a suppression", skip(transaction))]
+pub async fn insert_suppression(
+    transaction: &mut Transaction<'_, Postgres>,
+    target: &SuppressionTarget,
+    reason: &str,
+    source: &str,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let suppression = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (suppression_id, address, domain, reason, source, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT ((COALESCE(address, '@' || domain))) DO UPDATE
+        SET
+            suppression_id = EXCLUDED.suppression_id,
+            reason = EXCLUDED.reason,
+            source = EXCLUDED.source,
+            created_at = now(),
+            expires_at = EXCLUDED.expires_at
+        WHERE suppressions.expires_at <= now()
+        RETURNING suppression_id
+        "#,
+        Uuid::new_v4(),
+        target.address(),
+        target.domain(),
+        reason,
+        source,
+        expires_at,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM digest_entries
+        WHERE
+            lower(subscriber_email) = $1 OR
+            lower(split_part(subscriber_email, '@', 2)) = $2
+        "#,
+        target.address(),
+        target.domain(),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(suppression.map(|s| s.suppression_id))
+}
+
+// The addresses among `emails` that must not be sent to, lowercase.
+#[tracing::instrument(name = "Find suppressed addresses", skip(transaction))]
+pub async fn suppressed_addresses(
+    transaction: &mut Transaction<'_, Postgres>,
+    emails: &[String],
+) -> Result<HashSet<String>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT lower(t.email) as "email!"
+        FROM UNNEST($1::text[]) as t(email)
+        JOIN suppressions s ON
+            s.address = lower(t.email) OR
+            s.domain = lower(split_part(t.email, '@', 2))
+        WHERE s.expires_at IS NULL OR s.expires_at > now()
+        "#,
+        emails,
+    )
+    .fetch_all(transaction)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.email).collect())
+}
+
+pub fn is_suppressed(suppressed: &HashSet<String>, email: &str) -> bool {
+    suppressed.contains(&email.to_lowercase())
+}
+
+// Every send that was held back is recorded, which is what the suppressed send
+// counts in the admin API are computed from.
+#[tracing::instrument(name = "Record a suppressed send", skip(transaction))]
+pub async fn record_suppressed_send(
+    transaction: &mut Transaction<'_, Postgres>,
+    recipient: &str,
+    kind: SendKind,
+) -> Result<(), sqlx::Error> {
+    tracing::info!(
+        recipient,
+        kind = kind.as_str(),
+        "Not sending to a suppressed address"
+    );
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressed_sends (suppressed_send_id, recipient, kind, newsletter_issue_id)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        Uuid::new_v4(),
+        recipient,
+        kind.as_str(),
+        kind.newsletter_issue_id(),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,159 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn publish_issue(app: &TestApp) -> String {
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let page: serde_json::Value = app.get_admin("/issues").await.json().await.unwrap();
+    page["issues"][0]["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .to_owned()
+}
+
+async fn delivery_report(app: &TestApp, issue_id: &str) -> serde_json::Value {
+    app.get_admin(&format!("/issues/{}/deliveries", issue_id))
+        .await
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn successful_deliveries_are_recorded_with_the_provider_message_id() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202).insert_header("X-Message-Id", "msg-42"))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish_issue(&app).await;
+    let report = delivery_report(&app, &issue_id).await;
+    assert_eq!(report["pending"], 1);
+    app.dispatch_all_pending_emails().await;
+
+    let report = delivery_report(&app, &issue_id).await;
+    assert_eq!(report["pending"], 0);
+    assert_eq!(report["sent"], 1);
+    assert_eq!(report["failed"], 0);
+    let delivery = sqlx::query!("SELECT n_attempts, provider_message_id FROM deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the delivery");
+    assert_eq!(delivery.n_attempts, 1);
+    assert_eq!(delivery.provider_message_id.as_deref(), Some("msg-42"));
+}
+
+#[tokio::test]
+async fn rejected_deliveries_are_reported_as_failed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let report = delivery_report(&app, &issue_id).await;
+    assert_eq!(report["failed"], 1);
+    assert_eq!(report["sent"], 0);
+    let failure = &report["failures"][0];
+    assert_eq!(failure["subscriber_email"], "test@gmail.com");
+    assert_eq!(failure["n_attempts"], 1);
+    assert!(failure["last_error"].is_string());
+}
+
+#[tokio::test]
+async fn deliveries_to_subscribers_who_left_are_reported_as_skipped() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let issue_id = publish_issue(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let report = delivery_report(&app, &issue_id).await;
+    assert_eq!(report["skipped"], 1);
+    assert_eq!(report["sent"], 0);
+}
+
+#[tokio::test]
+async fn retrying_an_issue_only_resends_to_failed_recipients() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app).await;
+    {
+        let _mock_guard = Mock::given(path("/mail/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.dispatch_all_pending_emails().await;
+    }
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin(
+            &format!("/issues/{}/deliveries/retry", issue_id),
+            serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["retried"], 1);
+    app.dispatch_all_pending_emails().await;
+
+    let report = delivery_report(&app, &issue_id).await;
+    assert_eq!(report["sent"], 1);
+    assert_eq!(report["failed"], 0);
+
+    // Nothing is left to retry once every recipient has been reached.
+    let response = app
+        .post_admin(
+            &format!("/issues/{}/deliveries/retry", issue_id),
+            serde_json::json!({}),
+        )
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["retried"], 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn the_delivery_report_of_an_unknown_issue_is_a_404() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get_admin(&format!("/issues/{}/deliveries", Uuid::new_v4()))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,312 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn draft_body(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn create_draft(app: &TestApp, title: &str) -> String {
+    let response = app.post_admin("/issues", draft_body(title)).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let created: serde_json::Value = response.json().await.unwrap();
+    created["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+async fn schedule(
+    app: &TestApp,
+    issue_id: &str,
+    scheduled_for: DateTime<Utc>,
+) -> reqwest::Response {
+    app.post_admin(
+        &format!("/issues/{}/schedule", issue_id),
+        serde_json::json!({ "scheduled_for": scheduled_for }),
+    )
+    .await
+}
+
+// Issues cannot be scheduled in the past, so the clock is moved on instead.
+async fn make_due(app: &TestApp, issue_id: &str) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET scheduled_for = now() - interval '1 second' WHERE newsletter_issue_id = $1",
+        Uuid::parse_str(issue_id).unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn get_issue(app: &TestApp, issue_id: &str) -> serde_json::Value {
+    app.get_admin(&format!("/issues/{}", issue_id))
+        .await
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn drafts_are_stored_without_being_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = create_draft(&app, "Draft title").await;
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    let issue = get_issue(&app, &issue_id).await;
+    assert_eq!(issue["status"], "draft");
+    assert!(issue["published_at"].is_null());
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited_and_deleted() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Draft title").await;
+
+    let response = app
+        .put_admin(&format!("/issues/{}", issue_id), draft_body("New title"))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(get_issue(&app, &issue_id).await["title"], "New title");
+
+    let response = app.delete_admin(&format!("/issues/{}", issue_id)).await;
+    assert_eq!(response.status().as_u16(), 204);
+    let response = app.get_admin(&format!("/issues/{}", issue_id)).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn drafts_keep_their_markdown_source() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "title": "Draft title",
+        "content": { "markdown": "# Heading\n\nSome *text*" }
+    });
+
+    let response = app.post_admin("/issues", body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+    let created: serde_json::Value = response.json().await.unwrap();
+    let issue = get_issue(&app, created["newsletter_issue_id"].as_str().unwrap()).await;
+    assert_eq!(issue["markdown_content"], "# Heading\n\nSome *text*");
+    assert!(issue["html_content"]
+        .as_str()
+        .unwrap()
+        .contains("<h1>Heading</h1>"));
+    assert_eq!(issue["text_content"], "Heading\n\nSome text");
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_edited() {
+    let app = spawn_app().await;
+    app.post_newsletters(draft_body("Published title")).await;
+    let page: serde_json::Value = app.get_admin("/issues").await.json().await.unwrap();
+    let issue_id = page["issues"][0]["newsletter_issue_id"].as_str().unwrap();
+
+    let response = app
+        .put_admin(&format!("/issues/{}", issue_id), draft_body("New title"))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn previews_are_sent_to_the_configured_test_addresses_only() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app, "Draft title").await;
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin(
+            &format!("/issues/{}/preview", issue_id),
+            serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(
+        body["personalization"][0]["to"][0]["email"],
+        "preview@example.com"
+    );
+    assert_eq!(body["subject"], "[Preview] Draft title");
+    assert_eq!(get_issue(&app, &issue_id).await["status"], "draft");
+}
+
+#[tokio::test]
+async fn previews_are_rendered_with_placeholder_values() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "title": "Draft title",
+        "content": {
+            "text": "Hi {{ name }}",
+            "html": "<p>Hi {{ name }}</p>",
+        }
+    });
+    let response = app.post_admin("/issues", body).await;
+    let created: serde_json::Value = response.json().await.unwrap();
+    let issue_id = created["newsletter_issue_id"].as_str().unwrap();
+    Mock::given(path("/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin(
+            &format!("/issues/{}/preview", issue_id),
+            serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["content"][0]["value"], "Hi Preview Recipient");
+}
+
+#[tokio::test]
+async fn issues_with_unknown_template_variables_cannot_be_scheduled() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "title": "Draft title",
+        "content": {
+            "text": "Hi {{ nickname }}",
+            "html": "<p>Hi</p>",
+        }
+    });
+    let response = app.post_admin("/issues", body).await;
+    let created: serde_json::Value = response.json().await.unwrap();
+    let issue_id = created["newsletter_issue_id"].as_str().unwrap();
+
+    let response = schedule(&app, issue_id, Utc::now() + Duration::hours(1)).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.text().await.unwrap().contains("`nickname`"));
+    assert_eq!(get_issue(&app, issue_id).await["status"], "draft");
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_delivered_once_they_are_due() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app, "Draft title").await;
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = schedule(&app, &issue_id, Utc::now() + Duration::hours(1)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.publish_due_issues().await;
+    assert_eq!(get_issue(&app, &issue_id).await["status"], "scheduled");
+
+    make_due(&app, &issue_id).await;
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    let issue = get_issue(&app, &issue_id).await;
+    assert_eq!(issue["status"], "published");
+    assert!(!issue["published_at"].is_null());
+}
+
+#[tokio::test]
+async fn cancelled_issues_go_back_to_being_drafts() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app, "Draft title").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    schedule(&app, &issue_id, Utc::now() + Duration::hours(1)).await;
+    make_due(&app, &issue_id).await;
+
+    let response = app
+        .post_admin(
+            &format!("/issues/{}/cancel", issue_id),
+            serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    let issue = get_issue(&app, &issue_id).await;
+    assert_eq!(issue["status"], "draft");
+    assert!(issue["scheduled_for"].is_null());
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Draft title").await;
+
+    let response = schedule(&app, &issue_id, Utc::now() - Duration::seconds(1)).await;
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(get_issue(&app, &issue_id).await["status"], "draft");
+
+    // Rescheduling is held to the same rule.
+    let scheduled_for = Utc::now() + Duration::hours(1);
+    let response = schedule(&app, &issue_id, scheduled_for).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = schedule(&app, &issue_id, Utc::now() - Duration::hours(1)).await;
+    assert_eq!(response.status().as_u16(), 400);
+    let issue = get_issue(&app, &issue_id).await;
+    assert_eq!(issue["status"], "scheduled");
+    assert_eq!(
+        issue["scheduled_for"]
+            .as_str()
+            .unwrap()
+            .parse::<DateTime<Utc>>()
+            .unwrap()
+            .timestamp(),
+        scheduled_for.timestamp()
+    );
+}
+
+#[tokio::test]
+async fn only_scheduled_issues_can_be_cancelled() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Draft title").await;
+
+    let response = app
+        .post_admin(
+            &format!("/issues/{}/cancel", issue_id),
+            serde_json::json!({}),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
@@ -0,0 +1,142 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn issue_body(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn admin_endpoints_reject_requests_without_credentials() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/admin/issues", &app.address))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="admin""#
+    );
+}
+
+#[tokio::test]
+async fn published_issues_are_listed_newest_first_with_their_author() {
+    let app = spawn_app().await;
+    for title in ["First issue", "Second issue"] {
+        let response = app.post_newsletters(issue_body(title)).await;
+        assert_eq!(response.status().as_u16(), 202);
+    }
+
+    let response = app.get_admin("/issues").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let page: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(page["total"], 2);
+    let issues = page["issues"].as_array().unwrap();
+    assert_eq!(issues.len(), 2);
+    assert_eq!(issues[0]["title"], "Second issue");
+    assert_eq!(issues[1]["title"], "First issue");
+    assert_eq!(issues[0]["author"], app.test_user.username.as_str());
+    assert_eq!(issues[0]["status"], "published");
+}
+
+#[tokio::test]
+async fn issues_are_paginated() {
+    let app = spawn_app().await;
+    for title in ["First issue", "Second issue", "Third issue"] {
+        app.post_newsletters(issue_body(title)).await;
+    }
+
+    let page: serde_json::Value = app
+        .get_admin("/issues?page=2&page_size=2")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(page["total"], 3);
+    assert_eq!(page["page"], 2);
+    let issues = page["issues"].as_array().unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0]["title"], "First issue");
+}
+
+#[tokio::test]
+async fn invalid_pagination_parameters_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    for query in ["page=0", "page_size=0", "page_size=101"] {
+        let response = app.get_admin(&format!("/issues?{}", query)).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 for {}",
+            query
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_single_issue_can_be_fetched_with_its_content() {
+    let app = spawn_app().await;
+    app.post_newsletters(issue_body("Newsletter title")).await;
+    let page: serde_json::Value = app.get_admin("/issues").await.json().await.unwrap();
+    let issue_id = page["issues"][0]["newsletter_issue_id"].as_str().unwrap();
+
+    let response = app.get_admin(&format!("/issues/{}", issue_id)).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["title"], "Newsletter title");
+    assert!(issue["html_content"]
+        .as_str()
+        .unwrap()
+        .contains("<p>Newsletter body as HTML</p>"));
+    assert_eq!(issue["text_content"], "Newsletter body as plain text");
+}
+
+#[tokio::test]
+async fn fetching_an_unknown_issue_returns_a_404() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin(&format!("/issues/{}", Uuid::new_v4())).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn issue_stats_track_pending_and_processed_deliveries() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(issue_body("Newsletter title")).await;
+    let page: serde_json::Value = app.get_admin("/issues").await.json().await.unwrap();
+    let issue_id = page["issues"][0]["newsletter_issue_id"].as_str().unwrap();
+    let stats_path = format!("/issues/{}/stats", issue_id);
+
+    let stats: serde_json::Value = app.get_admin(&stats_path).await.json().await.unwrap();
+    assert_eq!(stats["recipients"], 1);
+    assert_eq!(stats["pending"], 1);
+    assert_eq!(stats["processed"], 0);
+
+    app.dispatch_all_pending_emails().await;
+
+    let stats: serde_json::Value = app.get_admin(&stats_path).await.json().await.unwrap();
+    assert_eq!(stats["recipients"], 1);
+    assert_eq!(stats["pending"], 0);
+    assert_eq!(stats["processed"], 1);
+}
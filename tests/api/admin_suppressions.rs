@@ -0,0 +1,249 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn suppression_stats(app: &TestApp) -> serde_json::Value {
+    app.get_admin("/suppressions/stats")
+        .await
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn suppressions_can_be_added_listed_and_removed() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_admin(
+            "/suppressions",
+            serde_json::json!({"address": "Ursula@Example.com", "reason": "Asked by phone"}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let created: serde_json::Value = response.json().await.unwrap();
+    let suppression_id = created["suppression_id"].as_str().unwrap();
+
+    let page: serde_json::Value = app.get_admin("/suppressions").await.json().await.unwrap();
+    assert_eq!(page["total"], 1);
+    let suppression = &page["suppressions"][0];
+    assert_eq!(suppression["suppression_id"], suppression_id);
+    assert_eq!(suppression["address"], "ursula@example.com");
+    assert_eq!(suppression["source"], "admin");
+    assert_eq!(suppression["active"], true);
+
+    let path = format!("/suppressions/{}", suppression_id);
+    assert_eq!(app.delete_admin(&path).await.status().as_u16(), 204);
+    assert_eq!(app.delete_admin(&path).await.status().as_u16(), 404);
+    let page: serde_json::Value = app.get_admin("/suppressions").await.json().await.unwrap();
+    assert_eq!(page["total"], 0);
+}
+
+#[tokio::test]
+async fn an_already_suppressed_target_is_rejected_with_a_409() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({"domain": "example.com", "reason": "Spam trap"});
+    app.post_admin("/suppressions", body.clone()).await;
+
+    let response = app.post_admin("/suppressions", body).await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn invalid_suppressions_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let test_cases = [
+        (serde_json::json!({"reason": "No target"}), "no target"),
+        (
+            serde_json::json!({"address": "not-an-address", "reason": "Typo"}),
+            "an invalid address",
+        ),
+        (
+            serde_json::json!({"address": "ursula@example.com", "reason": ""}),
+            "an empty reason",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_admin("/suppressions", body).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject a suppression with {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_not_sent_to_suppressed_domains() {
+    let app = spawn_app().await;
+    app.post_admin(
+        "/suppressions",
+        serde_json::json!({"domain": "gmail.com", "reason": "Spam trap"}),
+    )
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40Gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let stats = suppression_stats(&app).await;
+    assert_eq!(stats["active_suppressions"], 1);
+    assert_eq!(stats["suppressed_sends"], 1);
+    assert_eq!(stats["suppressed_transactional_sends"], 1);
+}
+
+#[tokio::test]
+async fn newsletters_are_not_sent_to_suppressed_addresses() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_admin(
+        "/suppressions",
+        serde_json::json!({"address": "test@gmail.com", "reason": "Asked by phone"}),
+    )
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let report: serde_json::Value = app
+        .get_admin(&format!("/issues/{}/deliveries", issue_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["skipped"], 1);
+    assert_eq!(
+        suppression_stats(&app).await["suppressed_newsletter_sends"],
+        1
+    );
+}
+
+#[tokio::test]
+async fn digests_held_back_by_a_suppression_are_counted_as_digest_sends() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET frequency = 'weekly_digest', last_digest_at = now() - interval '8 days'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+    app.queue_due_digests().await;
+
+    app.post_admin(
+        "/suppressions",
+        serde_json::json!({"address": "test@gmail.com", "reason": "Asked by phone"}),
+    )
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let stats = suppression_stats(&app).await;
+    assert_eq!(stats["suppressed_digest_sends"], 1);
+    assert_eq!(stats["suppressed_transactional_sends"], 0);
+}
+
+#[tokio::test]
+async fn expired_suppressions_no_longer_apply() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressions (suppression_id, address, reason, source, expires_at)
+        VALUES ($1, 'test@gmail.com', 'Temporary', 'admin', now() - interval '1 day')
+        "#,
+        Uuid::new_v4(),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20mans&email=test%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let stats = suppression_stats(&app).await;
+    assert_eq!(stats["active_suppressions"], 0);
+    assert_eq!(stats["suppressed_sends"], 0);
+    // An expired suppression can be replaced.
+    let response = app
+        .post_admin(
+            "/suppressions",
+            serde_json::json!({"address": "test@gmail.com", "reason": "Asked again"}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn hard_bounces_reported_by_the_provider_are_suppressed() {
+    let app = spawn_app().await;
+
+    app.post_email_events(serde_json::json!([{
+        "email": "Ursula@example.com",
+        "timestamp": 1677412800,
+        "event": "bounce",
+        "type": "bounce",
+        "reason": "550 5.1.1 The email account does not exist",
+        "sg_event_id": "event-1",
+    }]))
+    .await;
+
+    let page: serde_json::Value = app.get_admin("/suppressions").await.json().await.unwrap();
+    let suppression = &page["suppressions"][0];
+    assert_eq!(suppression["address"], "ursula@example.com");
+    assert_eq!(suppression["source"], "provider");
+    assert_eq!(
+        suppression["reason"],
+        "550 5.1.1 The email account does not exist"
+    );
+}
@@ -0,0 +1,165 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+fn event(event: &str, bounce_type: Option<&str>, event_id: &str) -> serde_json::Value {
+    serde_json::json!({
+        "email": "test@gmail.com",
+        "timestamp": 1677412800,
+        "event": event,
+        "type": bounce_type,
+        "reason": "550 5.1.1 The email account does not exist",
+        "sg_event_id": event_id,
+        "sg_message_id": "message-id",
+    })
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the subscriber")
+        .status
+}
+
+#[tokio::test]
+async fn a_hard_bounce_marks_the_subscriber_as_bounced() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = app
+        .post_email_events(serde_json::json!([event(
+            "bounce",
+            Some("bounce"),
+            "event-1"
+        )]))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "bounced");
+    let recorded = sqlx::query!("SELECT event, bounce_type, reason FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the recorded event");
+    assert_eq!(recorded.event, "bounce");
+    assert_eq!(recorded.bounce_type.as_deref(), Some("bounce"));
+}
+
+#[tokio::test]
+async fn a_temporary_bounce_keeps_the_subscriber_confirmed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    app.post_email_events(serde_json::json!([event(
+        "bounce",
+        Some("blocked"),
+        "event-1"
+    )]))
+    .await;
+
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn a_complaint_takes_precedence_over_a_bounce() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    app.post_email_events(serde_json::json!([
+        event("spamreport", None, "event-1"),
+        event("bounce", Some("bounce"), "event-2"),
+    ]))
+    .await;
+
+    assert_eq!(subscriber_status(&app).await, "complained");
+}
+
+#[tokio::test]
+async fn redelivered_events_are_recorded_once() {
+    let app = spawn_app().await;
+    let batch = serde_json::json!([event("delivered", None, "event-1")]);
+
+    for _ in 0..2 {
+        let response = app.post_email_events(batch.clone()).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let recorded = sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM email_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(recorded.count, 1);
+}
+
+#[tokio::test]
+async fn bounced_subscribers_do_not_receive_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_email_events(serde_json::json!([event(
+        "bounce",
+        Some("bounce"),
+        "event-1"
+    )]))
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn requests_without_a_valid_signature_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let body = serde_json::json!([event("spamreport", None, "event-1")]).to_string();
+    let timestamp = chrono::Utc::now().timestamp().to_string();
+
+    let test_cases = [
+        (None, "no signature"),
+        (Some("bm90IGEgc2lnbmF0dXJl"), "a wrong signature"),
+        (Some("not base64!"), "a malformed signature"),
+    ];
+    for (signature, description) in test_cases {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/webhooks/email-events", &app.address))
+            .header("X-Twilio-Email-Event-Webhook-Timestamp", &timestamp)
+            .body(body.clone());
+        if let Some(signature) = signature {
+            request = request.header("X-Twilio-Email-Event-Webhook-Signature", signature);
+        }
+        let response = request.send().await.expect("Failed to execute request");
+
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "The API did not reject a request with {}",
+            description
+        );
+    }
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn malformed_event_batches_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_email_events(serde_json::json!({"event": "bounce"}))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
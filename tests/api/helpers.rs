@@ -1,8 +1,20 @@
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::sync::Arc;
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::authentication::compute_password_hash;
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
+use zero2prod::digest_worker::try_queue_digest;
+use zero2prod::domain::Topic;
+use zero2prod::email_client::{EmailSender, RetryPolicy};
+use zero2prod::email_outbox_worker::try_dispatch_email;
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::issue_scheduler::try_publish_due_issue;
+use zero2prod::markdown::HtmlLayout;
+use zero2prod::routes::sign_email_events;
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
@@ -24,16 +36,84 @@ pub struct TestApp {
     pub port: u16,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub test_user: TestUser,
+    pub email_client: Arc<dyn EmailSender>,
+    pub base_url: String,
+    pub tracking_enabled: bool,
+    pub retry_policy: RetryPolicy,
+    pub webhook_signing_key: Secret<String>,
+    pub newsletter_layout: HtmlLayout,
+    pub enqueue_batch_size: i64,
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let password_hash = compute_password_hash(Secret::new(self.password.clone()))
+            .expect("Failed to hash test user password");
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash.expose_secret(),
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user");
+    }
 }
 
 pub struct ConfirmationLinks {
     pub html: reqwest::Url,
+    pub plain_text: reqwest::Url,
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_dispatch_email(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.retry_policy,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.base_url,
+                self.tracking_enabled,
+                &self.retry_policy,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -42,44 +122,214 @@ impl TestApp {
     }
 
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        let html = self.get_link(email_request, "text/html", "/subscriptions/confirm");
+        let plain_text = self.get_link(email_request, "text/plain", "/subscriptions/confirm");
+        ConfirmationLinks { html, plain_text }
+    }
+
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let unsubscribe_link =
+            self.get_link(email_request, "text/html", "/subscriptions/unsubscribe");
+        assert_eq!(
+            unsubscribe_link,
+            self.get_link(email_request, "text/plain", "/subscriptions/unsubscribe")
+        );
+        unsubscribe_link
+    }
+
+    pub fn get_preferences_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let preferences_link = self.get_link(email_request, "text/html", "/preferences");
+        assert_eq!(
+            preferences_link,
+            self.get_link(email_request, "text/plain", "/preferences")
+        );
+        preferences_link
+    }
+
+    fn get_link(
+        &self,
+        email_request: &wiremock::Request,
+        content_type: &str,
+        link_path: &str,
+    ) -> reqwest::Url {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
         let get_link = |s: &str| {
             let links: Vec<_> = linkify::LinkFinder::new()
                 .links(s)
                 .filter(|l| *l.kind() == linkify::LinkKind::Url)
+                .filter_map(|l| reqwest::Url::parse(l.as_str()).ok())
+                .filter(|l| l.path() == link_path)
                 .collect();
 
             assert_eq!(links.len(), 1);
-            let raw_link = links[0].as_str().to_owned();
-            let mut confirmation_link = reqwest::Url::parse(&raw_link).unwrap();
-            assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
-            confirmation_link.set_port(Some(self.port)).unwrap();
-            confirmation_link
+            let mut link = links[0].clone();
+            assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+            link.set_port(Some(self.port)).unwrap();
+            link
         };
 
-        let link_text = &body.as_object().unwrap()["content"].as_array().unwrap()[0]
-            .as_object()
+        let mut link_text = body["content"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|content| content["type"] == content_type)
             .unwrap()["value"]
             .as_str()
             .unwrap()
             .to_owned();
+        // Bulk sends carry per-recipient values as substitutions.
+        if let Some(substitutions) = body["personalization"][0]["substitutions"].as_object() {
+            for (placeholder, value) in substitutions {
+                link_text = link_text.replace(placeholder, value.as_str().unwrap());
+            }
+        }
 
-        let html = get_link(link_text);
-        ConfirmationLinks { html }
+        get_link(&link_text)
     }
 
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_admin(&self, path: &str, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn put_admin(&self, path: &str, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
             .await
             .expect("Failed to execute request")
     }
+
+    pub async fn delete_admin(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/admin{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_email_events(&self, events: serde_json::Value) -> reqwest::Response {
+        let body = serde_json::to_vec(&events).unwrap();
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let signature = sign_email_events(&self.webhook_signing_key, &timestamp, &body);
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/email-events", &self.address))
+            .header("X-Twilio-Email-Event-Webhook-Timestamp", timestamp)
+            .header("X-Twilio-Email-Event-Webhook-Signature", signature)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_preferences(&self, token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/preferences", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_preferences(&self, fields: &[(&str, &str)]) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/preferences", &self.address))
+            .form(fields)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn queue_due_digests(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_queue_digest(&self.db_pool, &self.base_url, &self.newsletter_layout)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    // Subscribers inserted straight into the database are on no list. This puts
+    // them on the default list with their current status.
+    pub async fn add_subscribers_to_default_list(&self) {
+        sqlx::query!(
+            r#"
+            INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+            SELECT l.list_id, s.id, s.status, s.subscribed_at
+            FROM subscriptions s, lists l
+            WHERE l.slug = 'newsletter'
+            ON CONFLICT DO NOTHING
+            "#
+        )
+        .execute(&self.db_pool)
+        .await
+        .unwrap();
+    }
+
+    pub async fn publish_due_issues(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_publish_due_issue(&self.db_pool, self.enqueue_batch_size)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -88,24 +338,51 @@ pub async fn spawn_app() -> TestApp {
         let mut c = get_configuration().expect("Failed to read properly");
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
+        c.application.preview_recipients = vec!["preview@example.com".into()];
+        c.application.newsletter_topics = vec![
+            Topic {
+                id: "rust".into(),
+                name: "Rust".into(),
+            },
+            Topic {
+                id: "python".into(),
+                name: "Python".into(),
+            },
+        ];
         c.email_client.base_url = email_server.uri();
+        configure(&mut c);
         c
     };
 
     configure_database(&configuration.database).await;
 
+    let email_client = configuration.email_client.clone().client();
     let application = Application::build(configuration.clone())
         .await
         .expect("Failed to build application");
     let application_port = application.port();
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
 
-    TestApp {
+    let newsletter_layout = configuration
+        .application
+        .newsletter_layout()
+        .expect("Invalid newsletter layout");
+    let test_app = TestApp {
         address: format!("http://localhost:{}", application_port),
         port: application_port,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
-    }
+        test_user: TestUser::generate(),
+        email_client,
+        base_url: configuration.application.base_url,
+        tracking_enabled: configuration.application.tracking_enabled,
+        retry_policy: configuration.email_client.retry.policy(),
+        webhook_signing_key: configuration.email_client.webhook_signing_key,
+        newsletter_layout,
+        enqueue_batch_size: configuration.application.enqueue_batch_size,
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
@@ -129,3 +406,39 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
 
     pool
 }
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20mans&email=test%40gmail.com";
+
+    let _mock_guard = Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
@@ -0,0 +1,314 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn create_list(app: &TestApp, slug: &str) {
+    let response = app
+        .post_admin(
+            "/lists",
+            serde_json::json!({"slug": slug, "name": format!("All about {}", slug)}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+// Subscribes through the form and follows the confirmation link.
+async fn subscribe_to_list(app: &TestApp, email: &str, list: &str) {
+    let _mock_guard = Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(format!(
+        "name=le%20guin&email={}&list={}",
+        email.replace('@', "%40"),
+        list
+    ))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn list_statuses(app: &TestApp, email: &str) -> Vec<(String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT l.slug, ls.status
+        FROM list_subscriptions ls
+        JOIN lists l ON l.list_id = ls.list_id
+        JOIN subscriptions s ON s.id = ls.subscriber_id
+        WHERE s.email = $1
+        ORDER BY l.slug
+        "#,
+        email
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.slug, r.status))
+    .collect()
+}
+
+fn statuses(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+    expected
+        .iter()
+        .map(|(slug, status)| (slug.to_string(), status.to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn lists_can_be_created_and_listed() {
+    let app = spawn_app().await;
+    create_list(&app, "rust").await;
+    create_confirmed_subscriber(&app).await;
+
+    let page: serde_json::Value = app.get_admin("/lists").await.json().await.unwrap();
+
+    let lists = page["lists"].as_array().unwrap();
+    assert_eq!(lists.len(), 2);
+    assert_eq!(lists[0]["slug"], "newsletter");
+    assert_eq!(lists[0]["confirmed_subscribers"], 1);
+    assert_eq!(lists[1]["slug"], "rust");
+    assert_eq!(lists[1]["name"], "All about rust");
+    assert_eq!(lists[1]["confirmed_subscribers"], 0);
+}
+
+#[tokio::test]
+async fn invalid_or_duplicate_lists_are_rejected() {
+    let app = spawn_app().await;
+    create_list(&app, "rust").await;
+
+    let duplicate = app
+        .post_admin(
+            "/lists",
+            serde_json::json!({"slug": "rust", "name": "Rust"}),
+        )
+        .await;
+    let invalid = app
+        .post_admin(
+            "/lists",
+            serde_json::json!({"slug": "Rust!", "name": "Rust"}),
+        )
+        .await;
+
+    assert_eq!(duplicate.status().as_u16(), 409);
+    assert_eq!(invalid.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=rust".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn each_list_is_confirmed_separately() {
+    let app = spawn_app().await;
+    create_list(&app, "rust").await;
+    create_confirmed_subscriber(&app).await;
+    let _mock_guard = Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20mans&email=test%40gmail.com&list=rust".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(
+        list_statuses(&app, "test@gmail.com").await,
+        statuses(&[
+            ("newsletter", "confirmed"),
+            ("rust", "pending_confirmation")
+        ])
+    );
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        list_statuses(&app, "test@gmail.com").await,
+        statuses(&[("newsletter", "confirmed"), ("rust", "confirmed")])
+    );
+}
+
+#[tokio::test]
+async fn issues_reach_the_subscribers_of_their_lists_once() {
+    let app = spawn_app().await;
+    create_list(&app, "rust").await;
+    create_list(&app, "python").await;
+    subscribe_to_list(&app, "alice@example.com", "rust").await;
+    subscribe_to_list(&app, "alice@example.com", "python").await;
+    subscribe_to_list(&app, "bob@example.com", "python").await;
+    subscribe_to_list(&app, "carol@example.com", "newsletter").await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "lists": ["rust", "python"],
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let queued =
+        sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue ORDER BY subscriber_email")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    let queued: Vec<_> = queued.into_iter().map(|r| r.subscriber_email).collect();
+    assert_eq!(queued, ["alice@example.com", "bob@example.com"]);
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let issue: serde_json::Value = app
+        .get_admin(&format!("/issues/{}", issue_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["lists"], serde_json::json!(["python", "rust"]));
+}
+
+#[tokio::test]
+async fn issues_for_an_unknown_list_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "lists": ["rust"],
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribers_can_leave_a_single_list() {
+    let app = spawn_app().await;
+    create_list(&app, "rust").await;
+    subscribe_to_list(&app, "alice@example.com", "newsletter").await;
+    subscribe_to_list(&app, "alice@example.com", "rust").await;
+    let token = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+    let unsubscribe = |list: Option<&str>| {
+        let mut url = format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+            app.address, token
+        );
+        if let Some(list) = list {
+            url.push_str(&format!("&list={}", list));
+        }
+        reqwest::Client::new().post(url).send()
+    };
+
+    assert_eq!(
+        unsubscribe(Some("cobol")).await.unwrap().status().as_u16(),
+        400
+    );
+    assert_eq!(
+        unsubscribe(Some("rust")).await.unwrap().status().as_u16(),
+        200
+    );
+    assert_eq!(
+        list_statuses(&app, "alice@example.com").await,
+        statuses(&[("newsletter", "confirmed"), ("rust", "unsubscribed")])
+    );
+
+    assert_eq!(unsubscribe(None).await.unwrap().status().as_u16(), 200);
+    assert_eq!(
+        list_statuses(&app, "alice@example.com").await,
+        statuses(&[("newsletter", "unsubscribed"), ("rust", "unsubscribed")])
+    );
+}
+
+#[tokio::test]
+async fn leaving_a_list_stops_the_queued_deliveries_of_its_issues() {
+    let app = spawn_app().await;
+    create_list(&app, "rust").await;
+    subscribe_to_list(&app, "alice@example.com", "newsletter").await;
+    subscribe_to_list(&app, "alice@example.com", "rust").await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "lists": ["rust"],
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    let token = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+    reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token={}&list=rust",
+            app.address, token
+        ))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    Mock::given(path("/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = sqlx::query!("SELECT status FROM deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "skipped");
+}
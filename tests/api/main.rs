@@ -1,5 +1,14 @@
+mod admin_deliveries;
+mod admin_drafts;
+mod admin_issues;
+mod admin_suppressions;
+mod email_events;
 mod health_check;
 mod helpers;
+mod lists;
 mod newsletter;
+mod preferences;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod tracking;
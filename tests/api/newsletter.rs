@@ -1,6 +1,8 @@
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
-use tracing_subscriber::fmt::format;
-use wiremock::matchers::{any, method, path};
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, spawn_app_with,
+};
+use uuid::Uuid;
+use wiremock::matchers::{any, body_string_contains, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
 #[tokio::test]
@@ -23,7 +25,8 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     });
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -48,9 +51,634 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
 
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
 }
 
+#[tokio::test]
+async fn publishing_enqueues_one_delivery_task_per_confirmed_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch queued delivery tasks");
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].subscriber_email, "test@gmail.com");
+    let issue = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved newsletter issue");
+    assert_eq!(issue.title, "Newsletter title");
+}
+
+#[tokio::test]
+async fn publishing_enqueues_every_confirmed_subscriber_and_skips_invalid_addresses() {
+    // Smaller than the number of subscribers, so they are queued over several
+    // batches.
+    let app = spawn_app_with(|c| c.application.enqueue_batch_size = 2).await;
+    for (email, status) in [
+        ("alice@example.com", "confirmed"),
+        ("bob@example.com", "confirmed"),
+        ("carol@example.com", "pending_confirmation"),
+        ("not-an-email", "confirmed"),
+    ] {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+            VALUES ($1, $2, 'name', now(), $3, $4)
+            "#,
+            Uuid::new_v4(),
+            email,
+            status,
+            Uuid::new_v4().to_string(),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    app.add_subscribers_to_default_list().await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let queued =
+        sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue ORDER BY subscriber_email")
+            .fetch_all(&app.db_pool)
+            .await
+            .expect("Failed to fetch queued delivery tasks");
+    let queued: Vec<_> = queued.into_iter().map(|r| r.subscriber_email).collect();
+    assert_eq!(queued, ["alice@example.com", "bob@example.com"]);
+    let issue = sqlx::query!("SELECT n_recipients FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved newsletter issue");
+    assert_eq!(issue.n_recipients, 2);
+}
+
+#[tokio::test]
+async fn an_issue_is_sent_to_many_subscribers_in_a_single_request() {
+    let app = spawn_app().await;
+    for email in ["alice@example.com", "bob@example.com", "carol@example.com"] {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+            VALUES ($1, $2, 'name', now(), 'confirmed', $3)
+            "#,
+            Uuid::new_v4(),
+            email,
+            Uuid::new_v4().to_string(),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    app.add_subscribers_to_default_list().await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let personalization = body["personalization"].as_array().unwrap();
+    assert_eq!(personalization.len(), 3);
+    let unsubscribe_urls: std::collections::HashSet<_> = personalization
+        .iter()
+        .map(|p| substitution(p, "unsubscribe_url"))
+        .collect();
+    assert_eq!(unsubscribe_urls.len(), 3);
+    let sent = sqlx::query!("SELECT count(*) as \"count!\" FROM deliveries WHERE status = 'sent'")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch deliveries");
+    assert_eq!(sent.count, 3);
+}
+
+#[tokio::test]
+async fn newsletters_are_personalized_for_each_subscriber() {
+    let app = spawn_app().await;
+    for (email, name) in [
+        ("alice@example.com", "Alice"),
+        ("bob@example.com", "Bob & Co"),
+    ] {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (
+                id, email, name, subscribed_at, status, unsubscribe_token, confirmed_at
+            )
+            VALUES ($1, $2, $3, now(), 'confirmed', $4, '2023-01-05T10:00:00Z')
+            "#,
+            Uuid::new_v4(),
+            email,
+            name,
+            Uuid::new_v4().to_string(),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    app.add_subscribers_to_default_list().await;
+    Mock::given(path("/mail/send"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Hi {{ name }}, subscribed since {{ confirmed_at }}",
+                "html": "<p>Hi {{name}}</p>",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(rendered_text(&body).starts_with("Hi Alice, subscribed since January 5, 2023"));
+    let substitutions: Vec<_> = body["personalization"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| {
+            (
+                substitution(p, "name").to_owned(),
+                substitution(p, "name|html").to_owned(),
+                substitution(p, "confirmed_at").to_owned(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        substitutions,
+        [
+            ("Alice".into(), "Alice".into(), "January 5, 2023".into()),
+            (
+                "Bob & Co".into(),
+                "Bob &amp; Co".into(),
+                "January 5, 2023".into()
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn newsletters_with_unknown_template_variables_are_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Hi {{ first_name }}",
+                "html": "<p>Hi {{ first_name }}</p>",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.text().await.unwrap().contains("`first_name`"));
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(issues.is_empty());
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn literal_braces_can_be_written_by_doubling_them() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/mail/send"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Write {{{{name}} to greet {{name}}",
+                "html": "<p>Write {{{{name}} to greet {{name}}</p>",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(rendered_text(&body).starts_with("Write {{name}} to greet le mans"));
+}
+
+#[tokio::test]
+async fn issues_queued_with_unknown_template_variables_are_still_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/mail/send"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    // As for an issue published before templating existed.
+    sqlx::query!("UPDATE newsletter_issues SET text_content = 'Hi {{ first_name }}'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["content"][0]["value"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hi {{ first_name }}"));
+}
+
+#[tokio::test]
+async fn newsletters_can_be_written_in_markdown() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/mail/send"))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "markdown": "Hello **{{ name }}**, read [the post](https://example.com/post).",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let issue =
+        sqlx::query!("SELECT html_content, text_content, markdown_content FROM newsletter_issues")
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("Failed to fetch saved newsletter issue");
+    assert!(issue
+        .html_content
+        .contains("<title>Newsletter title</title>"));
+    assert!(issue.html_content.contains("<strong>{{ name }}</strong>"));
+    assert_eq!(
+        issue.text_content,
+        "Hello {{ name }}, read the post (https://example.com/post)."
+    );
+    assert!(issue.markdown_content.unwrap().starts_with("Hello **"));
+
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["content"][1]["value"].as_str().unwrap();
+    assert!(html.trim_end().ends_with("</body>\n</html>"));
+    assert!(html.contains("unsubscribe</a> from this newsletter.</p>"));
+}
+
+#[tokio::test]
+async fn explicit_html_and_text_bodies_take_precedence_over_markdown() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "markdown": "Rendered *from* Markdown",
+                "text": "Hand-written plain text",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let issue = sqlx::query!("SELECT html_content, text_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved newsletter issue");
+    assert!(issue.html_content.contains("<em>from</em>"));
+    assert_eq!(issue.text_content, "Hand-written plain text");
+}
+
+#[tokio::test]
+async fn html_bodies_are_sanitized_before_being_stored() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": r#"<p onmouseover="steal()">Hi</p><script>alert(1)</script><img src="https://tracker.example.net/p.gif">"#,
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let issue = sqlx::query!("SELECT html_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved newsletter issue");
+    assert!(issue.html_content.contains("<p>Hi</p>"));
+    assert!(!issue.html_content.contains("script"));
+    assert!(!issue.html_content.contains("steal"));
+    assert!(!issue.html_content.contains("tracker.example.net"));
+}
+
+#[tokio::test]
+async fn inline_styles_survive_sanitization() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": r##"<table width="600" cellpadding="8"><tr><td style="color: #333333">Hi</td></tr></table>"##,
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let issue = sqlx::query!("SELECT html_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved newsletter issue");
+    assert!(issue
+        .html_content
+        .contains(r#"<table width="600" cellpadding="8">"#));
+    assert!(issue
+        .html_content
+        .contains(r##"<td style="color: #333333">Hi</td>"##));
+}
+
+#[tokio::test]
+async fn full_html_documents_are_not_wrapped_in_the_layout() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<!DOCTYPE html><html><head><title>Mine</title><script>alert(1)</script></head><body><p>Hi</p></body></html>",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let issue = sqlx::query!("SELECT html_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved newsletter issue");
+    assert_eq!(issue.html_content.matches("<html>").count(), 1);
+    assert_eq!(issue.html_content.matches("<body>").count(), 1);
+    assert!(issue.html_content.contains("<p>Hi</p>"));
+    assert!(!issue.html_content.contains("script"));
+}
+
+#[tokio::test]
+async fn malformed_or_oversized_html_is_rejected_with_details() {
+    let app = spawn_app().await;
+    let test_cases = [
+        ("<div><p>Unclosed</span>".to_string(), "</span>"),
+        (format!("<p>{}</p>", "a".repeat(100_000)), "limit"),
+    ];
+
+    for (html, detail) in test_cases {
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "content": { "text": "Newsletter body as plain text", "html": html }
+            }))
+            .await;
+
+        assert_eq!(response.status().as_u16(), 400);
+        assert!(response.text().await.unwrap().contains(detail));
+    }
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(issues.is_empty());
+}
+
+#[tokio::test]
+async fn a_failed_delivery_does_not_block_the_rest_of_the_queue() {
+    // One recipient per request, so a failure only concerns one of them.
+    let app = spawn_app_with(|c| c.email_client.max_recipients_per_request = 1).await;
+    for email in ["alice@example.com", "bob@example.com"] {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+            VALUES ($1, $2, 'name', now(), 'confirmed', $3)
+            "#,
+            Uuid::new_v4(),
+            email,
+            Uuid::new_v4().to_string(),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    app.add_subscribers_to_default_list().await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .and(body_string_contains("alice@example.com"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .and(body_string_contains("bob@example.com"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    let remaining = sqlx::query!(
+        r#"
+        SELECT subscriber_email, n_retries, execute_after > now() as "deferred!"
+        FROM issue_delivery_queue
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .expect("Failed to fetch queued delivery tasks");
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].subscriber_email, "alice@example.com");
+    assert_eq!(remaining[0].n_retries, 1);
+    assert!(remaining[0].deferred);
+    let deliveries = sqlx::query!(
+        "SELECT subscriber_email, status, n_attempts, last_error FROM deliveries ORDER BY subscriber_email"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .expect("Failed to fetch the deliveries");
+    assert_eq!(deliveries[0].subscriber_email, "alice@example.com");
+    assert_eq!(deliveries[0].status, "pending");
+    assert_eq!(deliveries[0].n_attempts, 1);
+    assert!(deliveries[0].last_error.is_some());
+    assert_eq!(deliveries[1].subscriber_email, "bob@example.com");
+    assert_eq!(deliveries[1].status, "sent");
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = Uuid::new_v4().to_string();
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_creation_is_handled_gracefully() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = Uuid::new_v4().to_string();
+    let response1 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key);
+    let response2 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_scoped_to_their_request_attempt() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..2 {
+        let idempotency_key = Uuid::new_v4().to_string();
+        let response = app
+            .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+            .await;
+        assert_eq!(response.status().as_u16(), 202);
+    }
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn invalid_idempotency_keys_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("".to_string(), "empty key"),
+        ("a".repeat(50), "key too long"),
+    ];
+
+    for (idempotency_key, description) in test_cases {
+        let response = app
+            .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+            .await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 bad request when the idempotency key was {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
 async fn newsletters_returns_400_for_invalid_data() {
     let app = spawn_app().await;
     let test_cases = vec![
@@ -67,6 +695,13 @@ async fn newsletters_returns_400_for_invalid_data() {
             serde_json::json!({"title": "Newsletter!"}),
             "missing content",
         ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": {"html": "<p>Newsletter body as HTML</p>"}
+            }),
+            "missing plain text and markdown body",
+        ),
     ];
 
     for (invalid_body, error_message) in test_cases {
@@ -81,37 +716,132 @@ async fn newsletters_returns_400_for_invalid_data() {
     }
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20mans&email=test%40gmail.com";
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
 
-    let _mock_guard = Mock::given(path("/mail/send"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
-        .expect(1)
-        .mount_as_scoped(&app.email_server)
-        .await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request");
 
-    app.post_subscriptions(body.into())
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn requests_with_malformed_authorization_are_rejected() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("Bearer some-token", "non-basic scheme"),
+        ("Basic not%base64", "invalid base64"),
+        ("Basic dXNlcm5hbWU=", "missing password"),
+    ];
+
+    for (authorization, description) in test_cases {
+        let response = reqwest::Client::new()
+            .post(format!("{}/newsletters", &app.address))
+            .header("Authorization", authorization)
+            .json(&newsletter_request_body())
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "The API did not reject the request when the authorization was {}",
+            description
+        );
+        assert_eq!(
+            r#"Basic realm="publish""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
         .await
-        .error_for_status()
-        .unwrap();
+        .expect("Failed to execute request");
 
-    let email_request = &app
-        .email_server
-        .received_requests()
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
         .await
+        .expect("Failed to execute request");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+// Placeholders carry a random marker: `{{name|<marker>}}` in plain text and
+// `{{name|html|<marker>}}` in HTML.
+fn substitution<'a>(personalization: &'a serde_json::Value, placeholder: &str) -> &'a str {
+    personalization["substitutions"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .find(|(key, _)| {
+            let key = key.trim_start_matches("{{").trim_end_matches("}}");
+            key.rsplit_once('|').map(|(prefix, _)| prefix) == Some(placeholder)
+        })
+        .and_then(|(_, value)| value.as_str())
         .unwrap()
-        .pop()
-        .unwrap();
-    app.get_confirmation_links(&email_request)
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
-        .await
+// What the first recipient gets once the provider has filled in the
+// placeholders of the plain text body.
+fn rendered_text(body: &serde_json::Value) -> String {
+    body["personalization"][0]["substitutions"]
+        .as_object()
         .unwrap()
-        .error_for_status()
-        .unwrap();
+        .iter()
+        .fold(
+            body["content"][0]["value"].as_str().unwrap().to_owned(),
+            |text, (placeholder, value)| text.replace(placeholder, value.as_str().unwrap()),
+        )
 }
@@ -0,0 +1,335 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscriber_token(app: &TestApp) -> String {
+    sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unsubscribe_token
+}
+
+async fn save_preferences(app: &TestApp, fields: &[(&str, &str)]) {
+    let token = subscriber_token(app).await;
+    let mut form = vec![("token", token.as_str())];
+    form.extend_from_slice(fields);
+    let response = app.post_preferences(&form).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+async fn pending_digest_entries(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM digest_entries"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+fn newsletter(title: &str, topic: Option<&str>) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "topic": topic,
+        "content": {
+            "text": format!("{} as plain text", title),
+            "html": format!("<p>{} as HTML</p>", title),
+        }
+    })
+}
+
+#[tokio::test]
+async fn preferences_with_an_unknown_token_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let page = app.get_preferences("unknown").await;
+    let update = app
+        .post_preferences(&[
+            ("token", "unknown"),
+            ("name", "Ursula"),
+            ("frequency", "every_issue"),
+        ])
+        .await;
+
+    assert_eq!(page.status().as_u16(), 401);
+    assert_eq!(update.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn newsletters_contain_a_working_preferences_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let _mock_guard = Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter("Newsletter title", None))
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+
+    let preferences_link = app.get_preferences_link(email_request);
+    let response = reqwest::get(preferences_link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"value="le mans""#));
+    assert!(html.contains(r#"value="rust"> Rust"#));
+    assert!(html.contains(r#"value="every_issue" checked"#));
+}
+
+#[tokio::test]
+async fn preferences_are_saved() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    save_preferences(
+        &app,
+        &[
+            ("name", "Ursula Le Guin"),
+            ("topics", "rust"),
+            ("topics", "python"),
+            ("frequency", "weekly_digest"),
+            ("pause_weeks", "2"),
+        ],
+    )
+    .await;
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT
+            name,
+            topics,
+            frequency,
+            paused_until > now() + interval '13 days' as "paused_for_two_weeks!"
+        FROM subscriptions
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.name, "Ursula Le Guin");
+    assert_eq!(saved.topics, ["python", "rust"]);
+    assert_eq!(saved.frequency, "weekly_digest");
+    assert!(saved.paused_for_two_weeks);
+
+    // A pause of 0 weeks resumes delivery.
+    save_preferences(
+        &app,
+        &[
+            ("name", "Ursula Le Guin"),
+            ("frequency", "every_issue"),
+            ("pause_weeks", "0"),
+        ],
+    )
+    .await;
+    let saved = sqlx::query!("SELECT topics, paused_until FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.topics.is_empty());
+    assert!(saved.paused_until.is_none());
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = subscriber_token(&app).await;
+    let test_cases = [
+        (vec![("name", "")], "an empty name"),
+        (
+            vec![("name", "Ursula"), ("topics", "cobol")],
+            "an unknown topic",
+        ),
+        (
+            vec![("name", "Ursula"), ("pause_weeks", "53")],
+            "a pause too long",
+        ),
+        (
+            vec![("name", "Ursula"), ("pause_weeks", "soon")],
+            "a pause that is not a number",
+        ),
+    ];
+
+    for (fields, description) in test_cases {
+        let mut form = vec![("token", token.as_str()), ("frequency", "every_issue")];
+        form.extend(fields);
+        let response = app.post_preferences(&form).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject preferences with {}",
+            description
+        );
+    }
+
+    let response = app
+        .post_preferences(&[
+            ("token", &token),
+            ("name", "Ursula"),
+            ("frequency", "daily"),
+        ])
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribers_only_receive_issues_on_the_topics_they_picked() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    save_preferences(
+        &app,
+        &[
+            ("name", "le mans"),
+            ("topics", "python"),
+            ("frequency", "every_issue"),
+        ],
+    )
+    .await;
+    let _mock_guard = Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter("About Rust", Some("rust")))
+        .await;
+    app.post_newsletters(newsletter("About Python", Some("python")))
+        .await;
+    app.post_newsletters(newsletter("About everything", None))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let bodies: Vec<_> = requests[1..]
+        .iter()
+        .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+        .collect();
+    assert!(bodies.iter().any(|b| b.contains("About Python")));
+    assert!(bodies.iter().any(|b| b.contains("About everything")));
+    assert!(!bodies.iter().any(|b| b.contains("About Rust")));
+}
+
+#[tokio::test]
+async fn paused_subscribers_do_not_receive_issues() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    save_preferences(
+        &app,
+        &[
+            ("name", "le mans"),
+            ("frequency", "every_issue"),
+            ("pause_weeks", "1"),
+        ],
+    )
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(newsletter("Newsletter title", None))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn weekly_digest_subscribers_receive_one_email_for_several_issues() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    save_preferences(&app, &[("name", "le mans"), ("frequency", "weekly_digest")]).await;
+    let mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter("First issue", None)).await;
+    app.post_newsletters(newsletter("Second issue", None)).await;
+    app.dispatch_all_pending_emails().await;
+    // The subscriber confirmed moments ago, so no digest is due yet.
+    app.queue_due_digests().await;
+    app.dispatch_all_pending_emails().await;
+    drop(mock_guard);
+
+    sqlx::query!("UPDATE subscriptions SET last_digest_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let _mock_guard = Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.queue_due_digests().await;
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    let html = body["content"][1]["value"].as_str().unwrap();
+    assert!(html.contains("<h2>First issue</h2>"));
+    assert!(html.find("First issue").unwrap() < html.find("Second issue").unwrap());
+    assert!(html.contains("/preferences?token="));
+    assert_eq!(pending_digest_entries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn pending_digest_entries_are_dropped_when_the_subscriber_unsubscribes() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    save_preferences(&app, &[("name", "le mans"), ("frequency", "weekly_digest")]).await;
+    app.post_newsletters(newsletter("First issue", None)).await;
+    assert_eq!(pending_digest_entries(&app).await, 1);
+
+    let token = subscriber_token(&app).await;
+    reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+            app.address, token
+        ))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    assert_eq!(pending_digest_entries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn pending_digest_entries_are_dropped_when_the_subscriber_is_suppressed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    save_preferences(&app, &[("name", "le mans"), ("frequency", "weekly_digest")]).await;
+    app.post_newsletters(newsletter("First issue", None)).await;
+    assert_eq!(pending_digest_entries(&app).await, 1);
+
+    let response = app
+        .post_admin(
+            "/suppressions",
+            serde_json::json!({"domain": "Gmail.com", "reason": "Spam trap"}),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(pending_digest_entries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn issues_with_an_unknown_topic_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(newsletter("Newsletter title", Some("cobol")))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
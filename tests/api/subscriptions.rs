@@ -1,5 +1,7 @@
-use crate::helpers::spawn_app;
-use wiremock::matchers::{method, path};
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, spawn_app_with,
+};
+use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
 #[tokio::test]
@@ -96,6 +98,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
         .await;
 
     let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     assert_eq!(200, response.status().as_u16());
 }
@@ -112,15 +115,129 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
-    let html_link = app.get_confirmation_links(&email_request);
+    let html_link = app.get_confirmation_links(email_request);
 
-    assert_eq!(
-        html_link.html.as_str(),
-        "http://127.0.0.1/subscriptions/confirm?subscription_token=token"
-    );
+    assert_eq!(html_link.html, html_link.plain_text);
+    assert_eq!(html_link.html.path(), "/subscriptions/confirm");
+    assert!(html_link
+        .html
+        .query_pairs()
+        .any(|(key, value)| key == "subscription_token" && value.len() == 25));
+}
+
+#[tokio::test]
+async fn subscribe_succeeds_while_the_email_provider_is_down() {
+    let app = spawn_app().await;
+    let body = "name=le%20mans&email=test%40gmail.com";
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let queued = sqlx::query!("SELECT recipient FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the queued confirmation email");
+    assert_eq!(queued.recipient, "test@gmail.com");
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_rescheduled_after_a_transient_failure() {
+    let app = spawn_app().await;
+    let body = "name=le%20mans&email=test%40gmail.com";
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("SELECT n_attempts, last_error FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the queued confirmation email");
+    assert_eq!(queued.n_attempts, 1);
+    assert!(queued.last_error.is_some());
+
+    sqlx::query!("UPDATE email_outbox SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let remaining = sqlx::query!("SELECT email_outbox_id FROM email_outbox")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(remaining.is_empty());
+}
+
+#[tokio::test]
+async fn confirmation_emails_wait_for_the_retry_after_delay_of_the_provider() {
+    let app = spawn_app().await;
+    let body = "name=le%20mans&email=test%40gmail.com";
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!(
+        "SELECT n_attempts, execute_after > now() + interval '59 minutes' AS \"deferred!\" \
+        FROM email_outbox"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch the queued confirmation email");
+    assert_eq!(queued.n_attempts, 1);
+    assert!(queued.deferred);
+}
+
+#[tokio::test]
+async fn confirmation_emails_fail_once_the_configured_attempts_are_used_up() {
+    let app = spawn_app_with(|c| c.email_client.retry.max_attempts = 1).await;
+    let body = "name=le%20mans&email=test%40gmail.com";
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let queued =
+        sqlx::query!(r#"SELECT n_attempts, failed_at IS NOT NULL as "failed!" FROM email_outbox"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("Failed to fetch the queued confirmation email");
+    assert_eq!(queued.n_attempts, 1);
+    assert!(queued.failed);
 }
 
 #[tokio::test]
@@ -135,3 +252,108 @@ async fn subscribe_fails_if_there_is_db_error() {
     let response = app.post_subscriptions(body.into()).await;
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email() {
+    let app = spawn_app().await;
+    let body = "name=le%20mans&email=test%40gmail.com";
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(200, response.status().as_u16());
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&email_requests[0]);
+    let second_link = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_link.html, second_link.html);
+
+    let response = reqwest::get(second_link.html).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn concurrent_first_time_subscriptions_for_the_same_email_both_succeed() {
+    let app = spawn_app().await;
+    let body = "name=le%20mans&email=test%40gmail.com";
+
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let (first, second) = tokio::join!(
+        app.post_subscriptions(body.into()),
+        app.post_subscriptions(body.into())
+    );
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, second.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribing_with_a_confirmed_email_returns_200_without_sending_an_email() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let body = "name=le%20mans&email=test%40gmail.com";
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_can_subscribe_again_through_double_opt_in() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let confirmation_link = create_unconfirmed_subscriber(&app).await;
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "pending_confirmation");
+    assert!(saved.unsubscribed_at.is_none());
+
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "confirmed");
+}
@@ -1,5 +1,4 @@
-use crate::helpers::spawn_app;
-use reqwest::Url;
+use crate::helpers::{create_unconfirmed_subscriber, spawn_app};
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -26,10 +25,10 @@ async fn link_returned_by_subscribe_returns_200_if_called() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
-    let confirmation_link = app.get_confirmation_links(&email_request);
+    let confirmation_link = app.get_confirmation_links(email_request);
 
     let response = reqwest::get(confirmation_link.html).await.unwrap();
 
@@ -48,10 +47,10 @@ async fn clicking_on_confirmation_link_confirms_subscriber() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
-    let confirmation_link = app.get_confirmation_links(&email_request);
+    let confirmation_link = app.get_confirmation_links(email_request);
 
     reqwest::get(confirmation_link.html).await.unwrap();
 
@@ -64,3 +63,123 @@ async fn clicking_on_confirmation_link_confirms_subscriber() {
     assert_eq!(saved.name, "le mans");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn confirmations_with_an_unknown_token_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_confirmation_link_can_only_be_used_once() {
+    let app = spawn_app().await;
+    let confirmation_link = create_unconfirmed_subscriber(&app).await;
+
+    let response = reqwest::get(confirmation_link.html.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = reqwest::get(confirmation_link.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn expired_confirmation_links_are_rejected_with_a_410() {
+    let app = spawn_app().await;
+    let confirmation_link = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_link.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscription_tokens_expire_after_the_configured_ttl() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+
+    let saved = sqlx::query!("SELECT created_at, expires_at FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription token");
+
+    assert_eq!(
+        saved.expires_at - saved.created_at,
+        chrono::Duration::hours(24)
+    );
+}
+
+#[tokio::test]
+async fn confirmations_for_subscribers_who_are_no_longer_pending_are_rejected_with_a_410() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let unsubscribe_token = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+    reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+            app.address, unsubscribe_token
+        ))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    let saved = sqlx::query!("SELECT status, confirmed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "unsubscribed");
+    assert!(saved.confirmed_at.is_none());
+}
+
+#[tokio::test]
+async fn stale_confirmation_links_are_rejected_after_subscribing_again() {
+    let app = spawn_app().await;
+    let stale_links = create_unconfirmed_subscriber(&app).await;
+    let unsubscribe_token = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+    reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+            app.address, unsubscribe_token
+        ))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let fresh_links = create_unconfirmed_subscriber(&app).await;
+
+    let stale = reqwest::get(stale_links.html).await.unwrap();
+    let fresh = reqwest::get(fresh_links.html).await.unwrap();
+
+    assert_eq!(stale.status().as_u16(), 410);
+    assert_eq!(fresh.status().as_u16(), 200);
+}
@@ -0,0 +1,231 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn unsubscribe_without_token_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/subscriptions/unsubscribe", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unsubscribe_with_an_unknown_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn opening_the_unsubscribe_link_asks_for_a_confirmation() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+    let response = reqwest::get(unsubscribe_link.clone()).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"method="post""#));
+    assert!(html.contains(unsubscribe_link.query().unwrap()));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn newsletters_contain_a_working_unsubscribe_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+    let response = unsubscribe(unsubscribe_link).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "unsubscribed");
+    assert!(saved.unsubscribed_at.is_some());
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+    unsubscribe(unsubscribe_link)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn queued_deliveries_are_skipped_after_unsubscribing() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    unsubscribe(unsubscribe_link)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_carry_one_click_unsubscribe_headers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let email_request = receive_newsletter(&app).await;
+
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let headers = &body["personalization"][0]["headers"];
+    let list_unsubscribe = headers["List-Unsubscribe"].as_str().unwrap();
+    assert!(list_unsubscribe.starts_with("<mailto:"));
+    assert!(list_unsubscribe.contains(unsubscribe_link.query().unwrap()));
+    assert_eq!(
+        headers["List-Unsubscribe-Post"],
+        "List-Unsubscribe=One-Click"
+    );
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_requests_are_accepted() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn bounced_and_complained_subscribers_keep_their_status_when_unsubscribing() {
+    for status in ["bounced", "complained"] {
+        let app = spawn_app().await;
+        create_confirmed_subscriber(&app).await;
+        let unsubscribe_token = sqlx::query!(
+            "UPDATE subscriptions SET status = $1 RETURNING unsubscribe_token",
+            status
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+        let unsubscribe_link = reqwest::Url::parse(&format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+            app.address, unsubscribe_token
+        ))
+        .unwrap();
+
+        let response = unsubscribe(unsubscribe_link).await;
+        // Subscribing again must not restart the subscription.
+        app.post_subscriptions("name=le%20mans&email=test%40gmail.com".into())
+            .await;
+
+        assert_eq!(response.status().as_u16(), 200);
+        let saved = sqlx::query!("SELECT status FROM subscriptions")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(saved.status, status);
+        let membership = sqlx::query!("SELECT status FROM list_subscriptions")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(membership.status, "unsubscribed");
+    }
+}
+
+async fn unsubscribe(unsubscribe_link: reqwest::Url) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn receive_unsubscribe_link(app: &TestApp) -> reqwest::Url {
+    let email_request = receive_newsletter(app).await;
+    app.get_unsubscribe_link(&email_request)
+}
+
+async fn receive_newsletter(app: &TestApp) -> wiremock::Request {
+    let _mock_guard = Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
@@ -0,0 +1,191 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, spawn_app_with, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body(tracking: bool) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Read more at https://example.com/post",
+            "html": r#"<p>Read <a href="https://example.com/post">more</a></p>"#,
+        },
+        "tracking": tracking,
+    })
+}
+
+async fn publish_and_dispatch(app: &TestApp, tracking: bool) -> Uuid {
+    create_confirmed_subscriber(app).await;
+    Mock::given(path("/mail/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(newsletter_request_body(tracking))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+// The HTML body of the last email sent, as the subscriber receives it.
+async fn received_html(app: &TestApp) -> String {
+    let email_request = app.email_server.received_requests().await.unwrap().pop();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.unwrap().body).unwrap();
+    let mut html = body["content"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|content| content["type"] == "text/html")
+        .unwrap()["value"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    if let Some(substitutions) = body["personalization"][0]["substitutions"].as_object() {
+        for (placeholder, value) in substitutions {
+            html = html.replace(placeholder, value.as_str().unwrap());
+        }
+    }
+    html
+}
+
+fn find_link(app: &TestApp, html: &str, path_prefix: &str) -> Option<reqwest::Url> {
+    linkify::LinkFinder::new()
+        .links(html)
+        .filter_map(|l| reqwest::Url::parse(l.as_str()).ok())
+        .find(|l| l.path().starts_with(path_prefix))
+        .map(|mut link| {
+            link.set_port(Some(app.port)).unwrap();
+            link
+        })
+}
+
+fn no_redirect_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn opens_and_clicks_of_tracked_issues_are_recorded() {
+    let app = spawn_app().await;
+    let issue_id = publish_and_dispatch(&app, true).await;
+    let html = received_html(&app).await;
+    assert!(!html.contains(r#"href="https://example.com/post""#));
+    let open_link = find_link(&app, &html, "/t/open/").expect("No open pixel");
+    let click_link = find_link(&app, &html, "/t/click/").expect("No tracked link");
+
+    let response = reqwest::get(open_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    let response = no_redirect_client().get(click_link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(response.headers()["Location"], "https://example.com/post");
+
+    let stats: serde_json::Value = app
+        .get_admin(&format!("/issues/{}/stats", issue_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["tracking_enabled"], true);
+    assert_eq!(stats["delivered"], 1);
+    assert_eq!(stats["unique_opens"], 1);
+    assert_eq!(stats["unique_clicks"], 1);
+    assert_eq!(stats["open_rate"], 1.0);
+    assert_eq!(stats["click_rate"], 1.0);
+}
+
+#[tokio::test]
+async fn repeated_opens_count_once_towards_the_open_rate() {
+    let app = spawn_app().await;
+    let issue_id = publish_and_dispatch(&app, true).await;
+    let open_link = find_link(&app, &received_html(&app).await, "/t/open/").unwrap();
+
+    for _ in 0..2 {
+        reqwest::get(open_link.clone()).await.unwrap();
+    }
+
+    let stats: serde_json::Value = app
+        .get_admin(&format!("/issues/{}/stats", issue_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["unique_opens"], 1);
+    assert_eq!(stats["open_rate"], 1.0);
+    assert_eq!(stats["click_rate"], 0.0);
+    let opens =
+        sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM tracking_events WHERE kind = 'open'"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(opens.count, 1);
+}
+
+#[tokio::test]
+async fn issues_are_not_tracked_unless_requested() {
+    let app = spawn_app().await;
+    let issue_id = publish_and_dispatch(&app, false).await;
+
+    let html = received_html(&app).await;
+
+    assert!(html.contains(r#"href="https://example.com/post""#));
+    assert!(find_link(&app, &html, "/t/open/").is_none());
+    let stats: serde_json::Value = app
+        .get_admin(&format!("/issues/{}/stats", issue_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["tracking_enabled"], false);
+}
+
+#[tokio::test]
+async fn tracking_can_be_disabled_entirely() {
+    let app = spawn_app_with(|c| c.application.tracking_enabled = false).await;
+    publish_and_dispatch(&app, true).await;
+
+    let html = received_html(&app).await;
+
+    assert!(html.contains(r#"href="https://example.com/post""#));
+    assert!(find_link(&app, &html, "/t/open/").is_none());
+    assert!(find_link(&app, &html, "/t/click/").is_none());
+}
+
+#[tokio::test]
+async fn unknown_tracked_links_are_rejected_with_a_404() {
+    let app = spawn_app().await;
+
+    let response = no_redirect_client()
+        .get(format!("{}/t/click/not-a-token?link=0", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_open_pixel_is_served_for_unknown_tokens() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/t/open/not-a-token", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let events = sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM tracking_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.count, 0);
+}